@@ -0,0 +1,6 @@
+//! The bits the converter and modelgen both need, so they quantize textures, write models and
+//! place things in Hammer the same way.
+
+pub mod mdl;
+pub mod palette;
+pub mod transform;
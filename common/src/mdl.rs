@@ -0,0 +1,589 @@
+use crate::palette;
+use std::collections::HashMap;
+
+// mstudiotexture_t flags
+const STUDIO_NF_MASKED: u32 = 0x40;
+
+// Limits from the engine's studio.h, going past them crashes or corrupts the model in game
+const MAXSTUDIOTRIANGLES: usize = 20000;
+const MAXSTUDIOVERTS: usize = 2048;
+const MAXSTUDIOSKINS: usize = 100;
+const MAXSTUDIOMESHES: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+pub struct Texture {
+    pub name: String,
+    pub image: palette::Indexed,
+    pub masked: bool,
+}
+
+pub struct Mesh {
+    /// Index into `Model::textures`.
+    pub texture: usize,
+    /// Counter-clockwise, like OBJ and SMD.
+    pub triangles: Vec<[Vertex; 3]>,
+}
+
+/// A static GoldSrc model: one bone, one bodypart, one idle sequence.
+pub struct Model {
+    pub name: String,
+    /// Yaw applied through the root bone. studiomdl adds 90 unless the QC has `$origin ... -90`.
+    pub rotation: f32,
+    pub textures: Vec<Texture>,
+    pub meshes: Vec<Mesh>,
+}
+
+struct Buf(Vec<u8>);
+
+impl Buf {
+    fn i32(&mut self, v: i32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn vec3(&mut self, v: [f32; 3]) {
+        for c in v {
+            self.f32(c);
+        }
+    }
+
+    fn name(&mut self, name: &str, len: usize) {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.truncate(len - 1);
+        bytes.resize(len, 0);
+        self.0.extend_from_slice(&bytes);
+    }
+
+    fn align(&mut self) {
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+    }
+
+    fn pos(&self) -> i32 {
+        self.0.len() as i32
+    }
+
+    fn patch(&mut self, at: usize, v: i32) {
+        self.0[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+// Tri command vertex: position index, normal index, s, t
+type CmdVertex = (i16, i16, i16, i16);
+
+/// Greedily chains triangles into GL-style strips. Each strip is a list of vertices, where
+/// triangle i is (v[i], v[i+1], v[i+2]) for even i and (v[i+1], v[i], v[i+2]) for odd i.
+fn build_strips(triangles: &[[CmdVertex; 3]]) -> Vec<Vec<CmdVertex>> {
+    // Directed edge -> triangles that have it, so (a, b) finds every (a, b, x) rotation
+    let mut edges: HashMap<(CmdVertex, CmdVertex), Vec<usize>> = HashMap::new();
+    for (i, [a, b, c]) in triangles.iter().enumerate() {
+        edges.entry((*a, *b)).or_default().push(i);
+        edges.entry((*b, *c)).or_default().push(i);
+        edges.entry((*c, *a)).or_default().push(i);
+    }
+
+    let mut used = vec![false; triangles.len()];
+    let mut taken = Vec::new();
+    let mut strips = Vec::new();
+
+    for start in 0..triangles.len() {
+        if used[start] {
+            continue;
+        }
+
+        // Any of the three rotations can start the strip, try each and put back what it took,
+        // then keep whichever runs longest
+        let [a, b, c] = triangles[start];
+        used[start] = true;
+        let first = [[a, b, c], [b, c, a], [c, a, b]]
+            .into_iter()
+            .max_by_key(|first| {
+                let strip = extend_strip(triangles, &edges, &mut used, &mut taken, first.to_vec());
+                for i in taken.drain(..) {
+                    used[i] = false;
+                }
+                strip.len()
+            })
+            .unwrap();
+
+        strips.push(extend_strip(
+            triangles,
+            &edges,
+            &mut used,
+            &mut taken,
+            first.to_vec(),
+        ));
+        taken.clear();
+    }
+
+    strips
+}
+
+fn extend_strip(
+    triangles: &[[CmdVertex; 3]],
+    edges: &HashMap<(CmdVertex, CmdVertex), Vec<usize>>,
+    used: &mut [bool],
+    taken: &mut Vec<usize>,
+    mut strip: Vec<CmdVertex>,
+) -> Vec<CmdVertex> {
+    loop {
+        let t = strip.len() - 2;
+        let (a, b) = (strip[t], strip[t + 1]);
+        // Odd triangles in a strip swap their first two vertices
+        let edge = if t.is_multiple_of(2) { (a, b) } else { (b, a) };
+        let Some(next) = edges
+            .get(&edge)
+            .and_then(|tris| tris.iter().find(|i| !used[**i]).copied())
+        else {
+            return strip;
+        };
+
+        let tri = triangles[next];
+        let Some(r) = (0..3).find(|r| (tri[*r], tri[(r + 1) % 3]) == edge) else {
+            return strip;
+        };
+        used[next] = true;
+        taken.push(next);
+        strip.push(tri[(r + 2) % 3]);
+    }
+}
+
+// Strip lengths and skin indices are shorts in the file
+fn short(n: usize, what: &str) -> anyhow::Result<i16> {
+    i16::try_from(n).map_err(|_| anyhow::anyhow!("too many {} for a GoldSrc model ({})", what, n))
+}
+
+fn check_limit(
+    name: &str,
+    count: usize,
+    what: &str,
+    limit: usize,
+    limit_name: &str,
+) -> anyhow::Result<()> {
+    if count > limit {
+        anyhow::bail!(
+            "{} has {} {}, over GoldSrc's {} of {}",
+            name,
+            count,
+            what,
+            limit_name,
+            limit
+        );
+    }
+    Ok(())
+}
+
+impl Model {
+    fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for vertex in self
+            .meshes
+            .iter()
+            .flat_map(|m| m.triangles.iter().flatten())
+        {
+            let [x, y, z] = vertex.pos;
+            let rotated = [x * cos - y * sin, x * sin + y * cos, z];
+            for c in 0..3 {
+                min[c] = min[c].min(rotated[c]);
+                max[c] = max[c].max(rotated[c]);
+            }
+        }
+        if min[0] > max[0] {
+            return ([0.; 3], [0.; 3]);
+        }
+        (min, max)
+    }
+
+    pub fn write(&self) -> anyhow::Result<Vec<u8>> {
+        let triangles = self.meshes.iter().map(|m| m.triangles.len()).sum();
+        check_limit(
+            &self.name,
+            triangles,
+            "triangles",
+            MAXSTUDIOTRIANGLES,
+            "MAXSTUDIOTRIANGLES",
+        )?;
+        check_limit(
+            &self.name,
+            self.meshes.len(),
+            "meshes",
+            MAXSTUDIOMESHES,
+            "MAXSTUDIOMESHES",
+        )?;
+        check_limit(
+            &self.name,
+            self.textures.len(),
+            "textures",
+            MAXSTUDIOSKINS,
+            "MAXSTUDIOSKINS",
+        )?;
+
+        let mut buf = Buf(Vec::new());
+        let (bbmin, bbmax) = self.bounds();
+
+        // studiohdr_t, offsets get patched in as each section is written
+        buf.0.extend_from_slice(b"IDST");
+        buf.i32(10);
+        buf.name(&format!("{}.mdl", self.name), 64);
+        let length_at = buf.0.len();
+        buf.i32(0);
+        buf.vec3([0.; 3]); // eye position
+        buf.vec3([0.; 3]); // movement hull
+        buf.vec3([0.; 3]);
+        buf.vec3(bbmin);
+        buf.vec3(bbmax);
+        buf.i32(0); // flags
+        let counts_at = buf.0.len();
+        buf.0.resize(buf.0.len() + 26 * 4, 0);
+        // Index of each header int after flags
+        let field = |i: usize| counts_at + i * 4;
+        let (bones, hitboxes, seqs, seqgroups, textures, skins, bodyparts) =
+            (0, 4, 6, 8, 10, 13, 16);
+
+        // Single root bone
+        buf.patch(field(bones), 1);
+        let bone_index = buf.pos();
+        buf.patch(field(bones + 1), bone_index);
+        buf.name("root", 32);
+        buf.i32(-1); // parent
+        buf.i32(0); // flags
+        for _ in 0..6 {
+            buf.i32(-1); // no bone controllers
+        }
+        for value in [0., 0., 0., 0., 0., self.rotation.to_radians()] {
+            buf.f32(value);
+        }
+        for scale in [1., 1., 1., 1., 1., 1.] {
+            buf.f32(scale);
+        }
+
+        // One hitbox around everything
+        buf.patch(field(hitboxes), 1);
+        let hitbox_index = buf.pos();
+        buf.patch(field(hitboxes + 1), hitbox_index);
+        buf.i32(0);
+        buf.i32(0);
+        buf.vec3(bbmin);
+        buf.vec3(bbmax);
+
+        // Idle sequence, one frame
+        buf.patch(field(seqs), 1);
+        let seq_index = buf.pos();
+        buf.patch(field(seqs + 1), seq_index);
+        buf.name("idle", 32);
+        buf.f32(1.); // fps
+        buf.i32(0); // flags
+        buf.i32(0); // activity
+        buf.i32(0); // activity weight
+        buf.i32(0); // events
+        buf.i32(0);
+        buf.i32(1); // frames
+        buf.i32(0); // pivots
+        buf.i32(0);
+        buf.i32(0); // motion type
+        buf.i32(0); // motion bone
+        buf.vec3([0.; 3]); // linear movement
+        buf.i32(0); // automove pos
+        buf.i32(0); // automove angle
+        buf.vec3(bbmin);
+        buf.vec3(bbmax);
+        buf.i32(1); // blends
+        let anim_index_at = buf.0.len();
+        buf.i32(0);
+        buf.0.resize(buf.0.len() + 6 * 4, 0); // blend types, starts and ends
+        buf.i32(0); // blend parent
+        buf.i32(0); // sequence group
+        buf.i32(0); // entry node
+        buf.i32(0); // exit node
+        buf.i32(0); // node flags
+        buf.i32(0); // next sequence
+
+        buf.patch(field(seqgroups), 1);
+        let seqgroup_index = buf.pos();
+        buf.patch(field(seqgroups + 1), seqgroup_index);
+        buf.name("default", 32);
+        buf.name("", 64);
+        buf.i32(0);
+        buf.i32(0);
+
+        // No animation data, so the bone's own values get used
+        let anim_index = buf.pos();
+        buf.patch(anim_index_at, anim_index);
+        buf.0.resize(buf.0.len() + 12, 0);
+
+        // Positions are shared across meshes, normals have to be contiguous per mesh because
+        // the engine lights them mesh by mesh
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut position_lookup: HashMap<[u32; 3], i16> = HashMap::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut mesh_data = Vec::new();
+
+        for mesh in &self.meshes {
+            let texture = &self.textures[mesh.texture].image;
+            let norm_start = normals.len();
+            let mut normal_lookup: HashMap<[u32; 3], i16> = HashMap::new();
+
+            let mut triangles = Vec::with_capacity(mesh.triangles.len());
+            for tri in &mesh.triangles {
+                // GoldSrc faces are clockwise, studiomdl flips SMD triangles the same way
+                let mut out = [(0, 0, 0, 0); 3];
+                for (v, out) in [tri[2], tri[1], tri[0]].iter().zip(&mut out) {
+                    let pos = match position_lookup.get(&v.pos.map(f32::to_bits)) {
+                        Some(pos) => *pos,
+                        None => {
+                            check_limit(
+                                &self.name,
+                                positions.len() + 1,
+                                "vertices",
+                                MAXSTUDIOVERTS,
+                                "MAXSTUDIOVERTS",
+                            )?;
+                            let pos = positions.len() as i16;
+                            positions.push(v.pos);
+                            position_lookup.insert(v.pos.map(f32::to_bits), pos);
+                            pos
+                        }
+                    };
+                    let normal = match normal_lookup.get(&v.normal.map(f32::to_bits)) {
+                        Some(normal) => *normal,
+                        None => {
+                            check_limit(
+                                &self.name,
+                                normals.len() + 1,
+                                "normals",
+                                MAXSTUDIOVERTS,
+                                "MAXSTUDIOVERTS",
+                            )?;
+                            let normal = normals.len() as i16;
+                            normals.push(v.normal);
+                            normal_lookup.insert(v.normal.map(f32::to_bits), normal);
+                            normal
+                        }
+                    };
+                    let s = (v.uv[0] * texture.width as f32).round() as i16;
+                    let t = ((1. - v.uv[1]) * texture.height as f32).round() as i16;
+                    *out = (pos, normal, s, t);
+                }
+                triangles.push(out);
+            }
+
+            mesh_data.push((mesh, build_strips(&triangles), normals.len() - norm_start));
+        }
+
+        // Body part -> model -> meshes
+        buf.align();
+        buf.patch(field(bodyparts), 1);
+        let bodypart_index = buf.pos();
+        buf.patch(field(bodyparts + 1), bodypart_index);
+        buf.name("studio", 64);
+        buf.i32(1); // models
+        buf.i32(1); // base
+        let model_index = buf.pos() + 4;
+        buf.i32(model_index);
+
+        buf.name(&self.name, 64);
+        buf.i32(0); // type
+        let radius = bbmin
+            .iter()
+            .chain(bbmax.iter())
+            .fold(0f32, |r, c| r.max(c.abs()));
+        buf.f32(radius * 3f32.sqrt());
+        buf.i32(mesh_data.len() as i32);
+        let mesh_index_at = buf.0.len();
+        buf.i32(0);
+        buf.i32(positions.len() as i32);
+        let vert_info_at = buf.0.len();
+        buf.i32(0);
+        let vert_at = buf.0.len();
+        buf.i32(0);
+        buf.i32(normals.len() as i32);
+        let norm_info_at = buf.0.len();
+        buf.i32(0);
+        let norm_at = buf.0.len();
+        buf.i32(0);
+        buf.i32(0); // groups
+        buf.i32(0);
+
+        let mesh_index = buf.pos();
+        buf.patch(mesh_index_at, mesh_index);
+        let mut tri_index_ats = Vec::new();
+        for (mesh, _, norm_count) in &mesh_data {
+            buf.i32(mesh.triangles.len() as i32);
+            tri_index_ats.push(buf.0.len());
+            buf.i32(0);
+            buf.i32(mesh.texture as i32); // skin ref
+            buf.i32(*norm_count as i32);
+            buf.i32(0);
+        }
+
+        // Everything is on bone 0
+        let vert_info = buf.pos();
+        buf.patch(vert_info_at, vert_info);
+        buf.0.resize(buf.0.len() + positions.len(), 0);
+        buf.align();
+        let norm_info = buf.pos();
+        buf.patch(norm_info_at, norm_info);
+        buf.0.resize(buf.0.len() + normals.len(), 0);
+        buf.align();
+
+        let vert = buf.pos();
+        buf.patch(vert_at, vert);
+        for pos in &positions {
+            buf.vec3(*pos);
+        }
+        let norm = buf.pos();
+        buf.patch(norm_at, norm);
+        for normal in &normals {
+            buf.vec3(*normal);
+        }
+
+        for ((_, strips, _), tri_index_at) in mesh_data.iter().zip(tri_index_ats) {
+            let tri_index = buf.pos();
+            buf.patch(tri_index_at, tri_index);
+            for strip in strips {
+                // Positive counts are strips, negative would be fans
+                buf.i16(short(strip.len(), "vertices in one strip")?);
+                for (pos, normal, s, t) in strip {
+                    buf.i16(*pos);
+                    buf.i16(*normal);
+                    buf.i16(*s);
+                    buf.i16(*t);
+                }
+            }
+            buf.i16(0);
+            buf.align();
+        }
+
+        // Texture headers and the skin table go before the pixel data, the engine drops
+        // everything past texturedataindex once the textures are uploaded
+        buf.patch(field(textures), self.textures.len() as i32);
+        let texture_index = buf.pos();
+        buf.patch(field(textures + 1), texture_index);
+        let mut data_index_ats = Vec::new();
+        for texture in &self.textures {
+            buf.name(&texture.name, 64);
+            buf.i32(if texture.masked {
+                STUDIO_NF_MASKED as i32
+            } else {
+                0
+            });
+            buf.i32(texture.image.width as i32);
+            buf.i32(texture.image.height as i32);
+            data_index_ats.push(buf.0.len());
+            buf.i32(0);
+        }
+
+        buf.patch(field(skins), self.textures.len() as i32); // skin refs
+        buf.patch(field(skins + 1), 1); // skin families
+        let skin_index = buf.pos();
+        buf.patch(field(skins + 2), skin_index);
+        for i in 0..self.textures.len() {
+            buf.i16(short(i, "textures")?);
+        }
+        buf.align();
+
+        let texture_data = buf.pos();
+        buf.patch(field(textures + 2), texture_data);
+        for (texture, data_index_at) in self.textures.iter().zip(data_index_ats) {
+            let data_index = buf.pos();
+            buf.patch(data_index_at, data_index);
+            buf.0.extend_from_slice(&texture.image.indices);
+            for color in &texture.image.palette {
+                buf.0.extend_from_slice(color);
+            }
+        }
+        buf.align();
+
+        let length = buf.pos();
+        buf.patch(length_at, length);
+        Ok(buf.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(size: i16) -> Vec<[CmdVertex; 3]> {
+        let v = |x: i16, y: i16| (x + y * (size + 1), 0, x, y);
+        let mut triangles = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                triangles.push([v(x, y), v(x + 1, y), v(x + 1, y + 1)]);
+                triangles.push([v(x, y), v(x + 1, y + 1), v(x, y + 1)]);
+            }
+        }
+        triangles
+    }
+
+    // Same triangle whichever vertex it starts on
+    fn normalize(tri: [CmdVertex; 3]) -> [CmdVertex; 3] {
+        let start = (0..3).min_by_key(|i| tri[*i]).unwrap();
+        [tri[start], tri[(start + 1) % 3], tri[(start + 2) % 3]]
+    }
+
+    #[test]
+    fn strips_cover_every_triangle_once() {
+        let triangles = grid(12);
+        let mut from_strips = Vec::new();
+        for strip in build_strips(&triangles) {
+            for t in 0..strip.len() - 2 {
+                let tri = if t % 2 == 0 {
+                    [strip[t], strip[t + 1], strip[t + 2]]
+                } else {
+                    [strip[t + 1], strip[t], strip[t + 2]]
+                };
+                from_strips.push(normalize(tri));
+            }
+        }
+        let mut expected = triangles.into_iter().map(normalize).collect::<Vec<_>>();
+        from_strips.sort();
+        expected.sort();
+        assert_eq!(from_strips, expected);
+    }
+
+    #[test]
+    fn too_many_vertices_names_the_limit() {
+        let vertex = |i: usize| Vertex {
+            pos: [i as f32, 0., 0.],
+            normal: [0., 0., 1.],
+            uv: [0., 0.],
+        };
+        let model = Model {
+            name: "big".to_string(),
+            rotation: 0.,
+            textures: vec![Texture {
+                name: "t.bmp".to_string(),
+                image: palette::Indexed {
+                    width: 16,
+                    height: 16,
+                    palette: vec![[0, 0, 0]; 256],
+                    indices: vec![0; 256],
+                },
+                masked: false,
+            }],
+            meshes: vec![Mesh {
+                texture: 0,
+                triangles: (0..700)
+                    .map(|i| [vertex(i * 3), vertex(i * 3 + 1), vertex(i * 3 + 2)])
+                    .collect(),
+            }],
+        };
+        let error = model.write().unwrap_err().to_string();
+        assert!(error.contains("MAXSTUDIOVERTS"), "{}", error);
+    }
+}
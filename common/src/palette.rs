@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+pub type Rgb = [u8; 3];
+
+struct ColorBox {
+    colors: Vec<(Rgb, usize)>,
+}
+
+impl ColorBox {
+    fn channel_range(&self, channel: usize) -> u8 {
+        let min = self
+            .colors
+            .iter()
+            .map(|(c, _)| c[channel])
+            .min()
+            .unwrap_or(0);
+        let max = self
+            .colors
+            .iter()
+            .map(|(c, _)| c[channel])
+            .max()
+            .unwrap_or(0);
+        max - min
+    }
+
+    fn widest_channel(&self) -> usize {
+        (0..3).max_by_key(|c| self.channel_range(*c)).unwrap()
+    }
+
+    fn weight(&self) -> usize {
+        self.colors.iter().map(|(_, n)| n).sum()
+    }
+
+    fn average(&self) -> Rgb {
+        let weight = self.weight().max(1);
+        let mut sum = [0usize; 3];
+        for (color, n) in &self.colors {
+            for c in 0..3 {
+                sum[c] += color[c] as usize * n;
+            }
+        }
+        sum.map(|s| ((s + weight / 2) / weight) as u8)
+    }
+
+    // Split at the weighted median of the widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let channel = self.widest_channel();
+        self.colors.sort_by_key(|(c, _)| c[channel]);
+
+        let half = self.weight() / 2;
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, n)) in self.colors.iter().enumerate() {
+            seen += n;
+            if seen >= half {
+                at = (i + 1).clamp(1, self.colors.len() - 1);
+                break;
+            }
+        }
+
+        let rest = self.colors.split_off(at);
+        (self, ColorBox { colors: rest })
+    }
+}
+
+/// Picks up to `max_colors` colors for the given pixels with median cut. If there are few enough
+/// unique colors they're used as-is.
+pub fn median_cut(pixels: impl Iterator<Item = Rgb>, max_colors: usize) -> Vec<Rgb> {
+    let mut counts: HashMap<Rgb, usize> = HashMap::new();
+    for pixel in pixels {
+        *counts.entry(pixel).or_default() += 1;
+    }
+
+    let mut colors = counts.into_iter().collect::<Vec<_>>();
+    // HashMap order isn't stable, and palettes should be
+    colors.sort();
+    if colors.len() <= max_colors {
+        return colors.into_iter().map(|(c, _)| c).collect();
+    }
+
+    let mut boxes = vec![ColorBox { colors }];
+    while boxes.len() < max_colors {
+        // Split whichever splittable box covers the most pixels over the widest range
+        let Some(index) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by_key(|(_, b)| b.weight() * b.channel_range(b.widest_channel()) as usize)
+            .map(|(i, _)| i)
+        else {
+            break;
+        };
+
+        let (a, b) = boxes.swap_remove(index).split();
+        boxes.push(a);
+        boxes.push(b);
+    }
+
+    boxes.iter().map(|b| b.average()).collect()
+}
+
+/// Index of the closest palette entry, by squared RGB distance.
+pub fn nearest(palette: &[Rgb], color: Rgb) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| {
+            (0..3)
+                .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+/// An 8bpp image: 256 palette entries and one index per pixel.
+pub struct Indexed {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<Rgb>,
+    pub indices: Vec<u8>,
+}
+
+impl Indexed {
+    /// Writes an 8bpp BMP, the only thing studiomdl takes.
+    pub fn write_bmp(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        image::codecs::bmp::BmpEncoder::new(&mut writer).encode_with_palette(
+            &self.indices,
+            self.width,
+            self.height,
+            image::ColorType::L8,
+            Some(&self.palette),
+        )?;
+        Ok(())
+    }
+}
+
+/// Maps every pixel to its closest palette entry, optionally with Floyd-Steinberg dithering.
+/// `None` pixels (transparent) always get index 255 and don't spread any error.
+pub fn remap(pixels: &[Option<Rgb>], width: usize, palette: &[Rgb], dither: bool) -> Vec<u8> {
+    let mut error = vec![[0f32; 3]; pixels.len()];
+    let mut indices = Vec::with_capacity(pixels.len());
+
+    for (i, pixel) in pixels.iter().enumerate() {
+        let Some(pixel) = pixel else {
+            indices.push(255);
+            continue;
+        };
+
+        let wanted = if dither {
+            [0, 1, 2].map(|c| (pixel[c] as f32 + error[i][c]).clamp(0., 255.))
+        } else {
+            pixel.map(|c| c as f32)
+        };
+        let index = nearest(palette, wanted.map(|c| c.round() as u8));
+        indices.push(index);
+
+        if dither {
+            let got = palette[index as usize];
+            let diff = [0, 1, 2].map(|c| wanted[c] - got[c] as f32);
+            let (x, y) = (i % width, i / width);
+            let height = pixels.len() / width;
+            for (dx, dy, weight) in [
+                (1, 0, 7. / 16.),
+                (-1, 1, 3. / 16.),
+                (0, 1, 5. / 16.),
+                (1, 1, 1. / 16.),
+            ] {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let n = ny as usize * width + nx as usize;
+                for c in 0..3 {
+                    error[n][c] += diff[c] * weight;
+                }
+            }
+        }
+    }
+
+    indices
+}
+
+/// Quantizes an image to 8 bits. With `masked`, anything not fully opaque goes to index 255,
+/// which is pure blue like GoldSrc's masked textures expect, and only 255 colors get used.
+pub fn quantize(image: &image::RgbaImage, masked: bool, dither: bool) -> Indexed {
+    let pixels = image
+        .pixels()
+        .map(|p| (!masked || p[3] == 255).then_some([p[0], p[1], p[2]]))
+        .collect::<Vec<_>>();
+
+    let mut palette = median_cut(
+        pixels.iter().flatten().copied(),
+        if masked { 255 } else { 256 },
+    );
+    let used = palette.len();
+    palette.resize(256, [0, 0, 0]);
+    if masked {
+        palette[255] = [0, 0, 255];
+    }
+
+    Indexed {
+        width: image.width(),
+        height: image.height(),
+        indices: remap(
+            &pixels,
+            image.width() as usize,
+            &palette[..used.max(1)],
+            dither,
+        ),
+        palette,
+    }
+}
@@ -0,0 +1,169 @@
+use serde::Deserialize;
+
+type Vec3 = (f64, f64, f64);
+
+/// GoldSrc maps have to fit in ±4096 on every axis.
+pub const GOLDSRC_LIMIT: f64 = 4096.;
+
+/// A way Hammer's axes can point, in Minecraft terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    East,
+    West,
+    Up,
+    Down,
+    South,
+    North,
+}
+
+impl Direction {
+    /// Which block axis (x east, y up, z south) this runs along, and whether it's backwards.
+    fn axis(self) -> (usize, bool) {
+        match self {
+            Direction::East => (0, false),
+            Direction::West => (0, true),
+            Direction::Up => (1, false),
+            Direction::Down => (1, true),
+            Direction::South => (2, false),
+            Direction::North => (2, true),
+        }
+    }
+}
+
+/// How block positions turn into Hammer units. Positions going in are in blocks, relative to the
+/// build's minimum corner, with Minecraft's axes (x east, y up, z south).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transform {
+    /// Hammer units per block.
+    pub scale: f64,
+    /// Added on at the end, in Hammer units.
+    pub offset: Vec3,
+    /// Which way Hammer's X, Y and Z point. Flipping one of them mirrors the map.
+    pub axes: [Direction; 3],
+    /// Ignore `offset` and put the middle of the build at the middle of the map instead.
+    pub center: bool,
+    /// The build's size in blocks, backwards axes count down from the far side.
+    #[serde(skip)]
+    size: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            scale: 48.,
+            offset: (0., -(4096. - 256.), -512.),
+            axes: [Direction::East, Direction::North, Direction::Up],
+            center: false,
+            size: (0., 0., 0.),
+        }
+    }
+}
+
+fn get(v: Vec3, axis: usize) -> f64 {
+    [v.0, v.1, v.2][axis]
+}
+
+impl Transform {
+    /// Fits the transform to a build this many blocks across.
+    pub fn with_size(mut self, size: (usize, usize, usize)) -> anyhow::Result<Self> {
+        let mut used = self.axes.map(|d| d.axis().0);
+        used.sort();
+        if used != [0, 1, 2] {
+            anyhow::bail!("transform axes {:?} don't cover all three axes", self.axes);
+        }
+
+        self.size = (size.0 as f64, size.1 as f64, size.2 as f64);
+        if self.center {
+            // Whole blocks, so the grid still lands on round numbers
+            let half = |i: usize| {
+                let (axis, _) = self.axes[i].axis();
+                -(get(self.size, axis) / 2.).floor() * self.scale
+            };
+            self.offset = (half(0), half(1), half(2));
+        }
+        Ok(self)
+    }
+
+    /// A position in blocks to Hammer units.
+    pub fn point(&self, pos: Vec3) -> Vec3 {
+        let along = |i: usize| {
+            let (axis, backwards) = self.axes[i].axis();
+            let value = get(pos, axis);
+            if backwards {
+                get(self.size, axis) - value
+            } else {
+                value
+            }
+        };
+        (
+            along(0) * self.scale + self.offset.0,
+            along(1) * self.scale + self.offset.1,
+            along(2) * self.scale + self.offset.2,
+        )
+    }
+
+    /// The other way around, for finding which block an entity is in.
+    pub fn block(&self, pos: Vec3) -> Vec3 {
+        let hammer = [
+            (pos.0 - self.offset.0) / self.scale,
+            (pos.1 - self.offset.1) / self.scale,
+            (pos.2 - self.offset.2) / self.scale,
+        ];
+        let mut block = [0.; 3];
+        for (i, direction) in self.axes.iter().enumerate() {
+            let (axis, backwards) = direction.axis();
+            block[axis] = if backwards {
+                get(self.size, axis) - hammer[i]
+            } else {
+                hammer[i]
+            };
+        }
+        (block[0], block[1], block[2])
+    }
+
+    /// Minimum and maximum Hammer corners of the box between two block-space corners.
+    pub fn bounds(&self, a: Vec3, b: Vec3) -> (Vec3, Vec3) {
+        let (a, b) = (self.point(a), self.point(b));
+        (
+            (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        )
+    }
+
+    /// Turns a direction given as (east, north, up), the way faces and block rotations are
+    /// worked out, into Hammer's axes. No scaling or offset.
+    pub fn direction(&self, v: Vec3) -> Vec3 {
+        let block = (v.0, v.2, -v.1);
+        let along = |i: usize| {
+            let (axis, backwards) = self.axes[i].axis();
+            let value = get(block, axis);
+            // Adding zero turns -0 into 0 for the output
+            let value = if backwards { -value } else { value };
+            value + 0.
+        };
+        (along(0), along(1), along(2))
+    }
+
+    /// Whether the axes turn the build inside out, like a mirror. Anything turning in the map
+    /// goes the other way around when they do.
+    pub fn mirrored(&self) -> bool {
+        let axes = self.axes.map(|d| d.axis());
+        let backwards = axes.iter().filter(|(_, backwards)| *backwards).count();
+        // Swapping two axes mirrors it too
+        let swaps = (0..3)
+            .flat_map(|i| (i + 1..3).map(move |j| (i, j)))
+            .filter(|&(i, j)| axes[i].0 > axes[j].0)
+            .count();
+        (backwards + swaps) % 2 == 1
+    }
+
+    /// Whether the whole build lands within `limit` units of the origin.
+    pub fn fits(&self, limit: f64) -> bool {
+        let (min, max) = self.bounds((0., 0., 0.), self.size);
+        [min.0, min.1, min.2, max.0, max.1, max.2]
+            .iter()
+            .all(|v| v.abs() <= limit)
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{nbt, util, Block};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+// Chunks from before 20w17a (1.16) pack block states tightly instead of padding each long
+const PADDED_STATES_VERSION: i32 = 2529;
+
+struct Region {
+    path: std::path::PathBuf,
+    data: Vec<u8>,
+}
+
+impl Region {
+    fn chunk(&self, cx: i32, cz: i32) -> anyhow::Result<Option<nbt::Tag>> {
+        let header = 4 * ((cx & 31) + (cz & 31) * 32) as usize;
+        if self.data.len() < 8192 {
+            return Ok(None);
+        }
+
+        let location = &self.data[header..header + 4];
+        let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+        if sector == 0 || location[3] == 0 {
+            // Never generated
+            return Ok(None);
+        }
+
+        let start = sector * 4096;
+        let Some(chunk) = self.data.get(start..start + 5) else {
+            anyhow::bail!("chunk {} {} points past the end of {:?}", cx, cz, self.path);
+        };
+        let length = u32::from_be_bytes(chunk[0..4].try_into()?) as usize;
+        let compression = chunk[4];
+
+        let payload = if compression & 0x80 != 0 {
+            // Oversized chunks live in their own file next to the region
+            let external = self.path.with_file_name(format!("c.{}.{}.mcc", cx, cz));
+            std::fs::read(&external)?
+        } else {
+            self.data
+                .get(start + 5..start + 4 + length)
+                .ok_or_else(|| anyhow::anyhow!("chunk {} {} is truncated", cx, cz))?
+                .to_vec()
+        };
+
+        match compression & 0x7f {
+            // gzip, zlib, uncompressed; nbt::read sniffs which one it is
+            1..=3 => Ok(Some(nbt::read(&payload)?)),
+            4 => anyhow::bail!(
+                "chunk {} {} uses LZ4 compression, which isn't supported",
+                cx,
+                cz
+            ),
+            c => anyhow::bail!("chunk {} {} has unknown compression {}", cx, cz, c),
+        }
+    }
+}
+
+/// Reads every block in the (inclusive) bounding box out of a world save's `region/*.mca` files.
+pub fn load(world: &str, min: (i32, i32, i32), max: (i32, i32, i32)) -> anyhow::Result<Vec<Block>> {
+    let (min, max) = (
+        (min.0.min(max.0), min.1.min(max.1), min.2.min(max.2)),
+        (min.0.max(max.0), min.1.max(max.1), min.2.max(max.2)),
+    );
+
+    let mut regions: HashMap<(i32, i32), Option<Region>> = HashMap::new();
+    let mut blocks = Vec::new();
+
+    for cx in (min.0 >> 4)..=(max.0 >> 4) {
+        for cz in (min.2 >> 4)..=(max.2 >> 4) {
+            let region = match regions.entry((cx >> 5, cz >> 5)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let path = std::path::Path::new(world).join("region").join(format!(
+                        "r.{}.{}.mca",
+                        cx >> 5,
+                        cz >> 5
+                    ));
+                    // No file just means nobody's been there yet
+                    let region = match std::fs::read(&path) {
+                        Ok(data) => Some(Region { path, data }),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                        Err(e) => {
+                            return Err(anyhow::Error::from(e)
+                                .context(format!("reading {}", path.display())))
+                        }
+                    };
+                    entry.insert(region)
+                }
+            };
+            let Some(chunk) = region
+                .as_ref()
+                .map(|r| r.chunk(cx, cz))
+                .transpose()?
+                .flatten()
+            else {
+                continue;
+            };
+
+            let data_version = chunk
+                .get("DataVersion")
+                .and_then(|t| t.as_i32())
+                .unwrap_or(0);
+            // 1.18 flattened the chunk and renamed everything
+            let (sections, palette_key, states_key) = match chunk.get("sections") {
+                Some(sections) => (sections, "palette", "data"),
+                None => (
+                    chunk
+                        .get("Level")
+                        .and_then(|l| l.get("Sections"))
+                        .ok_or_else(|| anyhow::anyhow!("chunk {} {} has no sections", cx, cz))?,
+                    "Palette",
+                    "BlockStates",
+                ),
+            };
+
+            for section in sections.as_list().unwrap_or_default() {
+                // Signed, so sections below zero (1.18+) come out negative
+                let sy = section.int("Y")?;
+                if sy * 16 > max.1 || sy * 16 + 15 < min.1 {
+                    continue;
+                }
+
+                let states = section.get("block_states").unwrap_or(section);
+                let Some(palette) = states.get(palette_key).and_then(|p| p.as_list()) else {
+                    // Empty sections in old chunks have no palette at all
+                    continue;
+                };
+                let palette = palette
+                    .iter()
+                    .map(|state| Block::from_nbt_state((0, 0, 0), state))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                // Single-entry palettes omit the data array, everything is index 0
+                let data = states
+                    .get(states_key)
+                    .and_then(|d| d.as_long_array())
+                    .unwrap_or_default();
+
+                let bits =
+                    (usize::BITS - (palette.len().max(1) - 1).leading_zeros()).max(4) as usize;
+                let padded = data_version >= PADDED_STATES_VERSION;
+                let needed = if padded {
+                    4096usize.div_ceil(64 / bits)
+                } else {
+                    (4096 * bits).div_ceil(64)
+                };
+                if !data.is_empty() && data.len() < needed {
+                    anyhow::bail!("section {} of chunk {} {} is truncated", sy, cx, cz);
+                }
+
+                for ly in 0..16 {
+                    let y = sy * 16 + ly;
+                    if y < min.1 || y > max.1 {
+                        continue;
+                    }
+                    for lz in 0..16 {
+                        let z = cz * 16 + lz;
+                        if z < min.2 || z > max.2 {
+                            continue;
+                        }
+                        for lx in 0..16 {
+                            let x = cx * 16 + lx;
+                            if x < min.0 || x > max.0 {
+                                continue;
+                            }
+
+                            let index = ((ly * 16 + lz) * 16 + lx) as usize;
+                            let state = match (data.is_empty(), padded) {
+                                (true, _) => 0,
+                                (false, true) => util::padded_value(data, bits, index),
+                                (false, false) => util::packed_value(data, bits, index),
+                            };
+                            let Some(Some(block)) = palette.get(state) else {
+                                continue;
+                            };
+
+                            blocks.push(Block {
+                                pos: (x, y, z),
+                                ..block.clone()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(blocks)
+}
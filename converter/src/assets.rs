@@ -0,0 +1,248 @@
+use crate::mapping::BlockTextures;
+use crate::util::{Face, Rotation};
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Reads blockstates and block models out of an unpacked resource pack or client jar.
+pub struct Assets {
+    root: PathBuf,
+    // Keyed by id and property string, models get looked up once per box otherwise
+    cache: RefCell<HashMap<(String, String), Option<BlockTextures>>>,
+    // Files that failed to parse, so each only gets complained about once
+    broken: RefCell<HashSet<PathBuf>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Variant {
+    model: String,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Variants {
+    One(Variant),
+    // Weighted random pick, the first one will do
+    Many(Vec<Variant>),
+}
+
+impl Variants {
+    fn into_first(self) -> Option<Variant> {
+        match self {
+            Variants::One(variant) => Some(variant),
+            Variants::Many(variants) => variants.into_iter().next(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Part {
+    when: Option<Value>,
+    apply: Variants,
+}
+
+#[derive(Debug, Deserialize)]
+struct Blockstate {
+    variants: Option<HashMap<String, Variants>>,
+    multipart: Option<Vec<Part>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Model {
+    parent: Option<String>,
+    #[serde(default)]
+    textures: HashMap<String, String>,
+    elements: Option<Vec<Element>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Element {
+    from: [f64; 3],
+    to: [f64; 3],
+    #[serde(default)]
+    faces: HashMap<String, ElementFace>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ElementFace {
+    texture: String,
+}
+
+fn strip_namespace(name: &str) -> &str {
+    name.split_once(':').map(|(_, n)| n).unwrap_or(name)
+}
+
+fn face_name(face: Face) -> &'static str {
+    match face {
+        Face::North => "north",
+        Face::South => "south",
+        Face::East => "east",
+        Face::West => "west",
+        Face::Top => "up",
+        Face::Bottom => "down",
+    }
+}
+
+/// Whether a blockstate `key=value,...` string or multipart `when` matches the properties.
+fn matches_key(key: &str, props: &HashMap<String, String>) -> bool {
+    key.split(',').filter(|p| !p.is_empty()).all(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        props.get(k).is_some_and(|p| p == v)
+    })
+}
+
+fn matches_when(when: &Value, props: &HashMap<String, String>) -> bool {
+    let Some(when) = when.as_object() else {
+        return false;
+    };
+    if let Some(any) = when.get("OR").and_then(|o| o.as_array()) {
+        return any.iter().any(|w| matches_when(w, props));
+    }
+    if let Some(all) = when.get("AND").and_then(|o| o.as_array()) {
+        return all.iter().all(|w| matches_when(w, props));
+    }
+    when.iter().all(|(k, v)| {
+        let wanted = match v {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        props
+            .get(k)
+            .is_some_and(|p| wanted.split('|').any(|w| w == p))
+    })
+}
+
+impl Assets {
+    /// Takes either the pack root or its `assets/minecraft` directory.
+    pub fn new(dir: &str) -> anyhow::Result<Self> {
+        let dir = Path::new(dir);
+        let root = [dir.join("assets").join("minecraft"), dir.to_path_buf()]
+            .into_iter()
+            .find(|d| d.join("blockstates").is_dir())
+            .ok_or_else(|| anyhow::anyhow!("{:?} has no blockstates directory", dir))?;
+        Ok(Self {
+            root,
+            cache: RefCell::new(HashMap::new()),
+            broken: RefCell::new(HashSet::new()),
+        })
+    }
+
+    // Missing files are normal (builtin models, blocks the pack doesn't have), broken ones get
+    // reported and then treated as missing
+    fn read<T: for<'a> Deserialize<'a>>(&self, kind: &str, name: &str) -> Option<T> {
+        let path = self.root.join(kind).join(format!("{}.json", name));
+        let result = match std::fs::read_to_string(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => Err(anyhow::Error::from(e)),
+            Ok(text) => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        };
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                if self.broken.borrow_mut().insert(path.clone()) {
+                    println!("Couldn't read {}: {}", path.display(), e);
+                }
+                None
+            }
+        }
+    }
+
+    fn variant(&self, id: &str, props: &HashMap<String, String>) -> Option<Variant> {
+        let state: Blockstate = self.read("blockstates", id)?;
+        if let Some(variants) = state.variants {
+            let (_, variants) = variants.into_iter().find(|(k, _)| matches_key(k, props))?;
+            return variants.into_first();
+        }
+
+        // Full cubes don't really use multipart, but take the first matching part anyway
+        state
+            .multipart?
+            .into_iter()
+            .find(|p| p.when.as_ref().is_none_or(|w| matches_when(w, props)))
+            .and_then(|p| p.apply.into_first())
+    }
+
+    /// Walks the parent chain, merging texture variables and keeping the closest elements.
+    fn model(&self, name: &str) -> Option<Model> {
+        let mut merged = Model::default();
+        let mut next = Some(name.to_string());
+        // Parent chains are short, this only guards against loops
+        for _ in 0..32 {
+            let Some(name) = next.take() else {
+                break;
+            };
+            // Anything without a file (builtin/*, or a missing parent) just ends the chain
+            let Some(model) = self.read::<Model>("models", strip_namespace(&name)) else {
+                break;
+            };
+            for (k, v) in model.textures {
+                merged.textures.entry(k).or_insert(v);
+            }
+            if merged.elements.is_none() {
+                merged.elements = model.elements;
+            }
+            next = model.parent;
+        }
+        Some(merged)
+    }
+
+    fn resolve_texture(textures: &HashMap<String, String>, texture: &str) -> Option<String> {
+        let mut texture = texture.to_string();
+        for _ in 0..32 {
+            let Some(variable) = texture.strip_prefix('#') else {
+                // minecraft:block/oak_log_top -> oak_log_top, same as the PNGs are named
+                return strip_namespace(&texture)
+                    .rsplit('/')
+                    .next()
+                    .map(|s| s.to_string());
+            };
+            texture = textures.get(variable)?.clone();
+        }
+        None
+    }
+
+    fn resolve(&self, id: &str, props: &HashMap<String, String>) -> Option<BlockTextures> {
+        let variant = self.variant(id, props)?;
+        let model = self.model(&variant.model)?;
+
+        // Only full cubes map onto a box brush
+        let elements = model.elements?;
+        let [element] = elements.as_slice() else {
+            return None;
+        };
+        if element.from != [0., 0., 0.] || element.to != [16., 16., 16.] {
+            return None;
+        }
+
+        let faces = Face::ALL.map(|face| {
+            let model_face = element.faces.get(face_name(face))?;
+            Self::resolve_texture(&model.textures, &model_face.texture)
+        });
+        Some(BlockTextures {
+            faces,
+            rotation: Rotation {
+                x: variant.x,
+                y: variant.y,
+            },
+        })
+    }
+
+    /// Face textures and the variant's rotation for a full-cube block, if the assets describe it.
+    pub fn textures(&self, id: &str, props: &HashMap<String, String>) -> Option<BlockTextures> {
+        let mut key_props = props.iter().collect::<Vec<_>>();
+        key_props.sort();
+        let key = (id.to_string(), format!("{:?}", key_props));
+
+        self.cache
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| self.resolve(id, props))
+            .clone()
+    }
+}
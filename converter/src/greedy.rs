@@ -1,15 +1,44 @@
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap};
 
 #[derive(Debug, Clone)]
 pub struct Voxel {
     pub id: String,
     pub properties: String,
+    /// Which axes (x, y, z) a box of this voxel can grow along. Shaped blocks like stairs stop
+    /// being the right shape when they get stretched the wrong way.
+    pub stretch: [bool; 3],
+    /// Whether boxes of this voxel can overlap each other. Fewer brushes for the world, where the
+    /// compilers cut them up anyway, but brush entities draw every face, inside ones included.
+    pub overlap: bool,
 }
 
 impl Voxel {
     pub fn new(id: String, properties: String) -> Self {
-        Self { id, properties }
+        Self {
+            id,
+            properties,
+            stretch: [true; 3],
+            overlap: false,
+        }
+    }
+
+    pub fn with_overlap(mut self, overlap: bool) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn with_stretch(mut self, stretch: [bool; 3]) -> Self {
+        self.stretch = stretch;
+        self
+    }
+
+    fn stretches(&self, dir: GreedyDirection) -> bool {
+        match dir {
+            GreedyDirection::X => self.stretch[0],
+            GreedyDirection::Y => self.stretch[1],
+            GreedyDirection::Z => self.stretch[2],
+        }
     }
 }
 
@@ -27,29 +56,6 @@ pub struct Box {
     pub properties: String,
 }
 
-impl Box {
-    pub fn contains(&self, pos: (usize, usize, usize)) -> bool {
-        pos.0 >= self.min.0
-            && pos.0 <= self.max.0
-            && pos.1 >= self.min.1
-            && pos.1 <= self.max.1
-            && pos.2 >= self.min.2
-            && pos.2 <= self.max.2
-    }
-
-    pub fn all_positions(&self) -> Vec<(usize, usize, usize)> {
-        let mut positions = Vec::new();
-        for x in self.min.0..=self.max.0 {
-            for y in self.min.1..=self.max.1 {
-                for z in self.min.2..=self.max.2 {
-                    positions.push((x, y, z));
-                }
-            }
-        }
-        positions
-    }
-}
-
 #[derive(Debug, Clone, Copy)]
 pub enum GreedyDirection {
     X,
@@ -57,132 +63,306 @@ pub enum GreedyDirection {
     Z,
 }
 
+impl GreedyDirection {
+    fn of(self, pos: (usize, usize, usize)) -> usize {
+        match self {
+            GreedyDirection::X => pos.0,
+            GreedyDirection::Y => pos.1,
+            GreedyDirection::Z => pos.2,
+        }
+    }
+
+    fn with(self, pos: (usize, usize, usize), value: usize) -> (usize, usize, usize) {
+        match self {
+            GreedyDirection::X => (value, pos.1, pos.2),
+            GreedyDirection::Y => (pos.0, value, pos.2),
+            GreedyDirection::Z => (pos.0, pos.1, value),
+        }
+    }
+}
+
+const DIRECTIONS: [[GreedyDirection; 3]; 6] = [
+    [GreedyDirection::X, GreedyDirection::Y, GreedyDirection::Z],
+    [GreedyDirection::X, GreedyDirection::Z, GreedyDirection::Y],
+    [GreedyDirection::Y, GreedyDirection::X, GreedyDirection::Z],
+    [GreedyDirection::Y, GreedyDirection::Z, GreedyDirection::X],
+    [GreedyDirection::Z, GreedyDirection::X, GreedyDirection::Y],
+    [GreedyDirection::Z, GreedyDirection::Y, GreedyDirection::X],
+];
+
+/// Dense grid over the voxels' bounding box, with which cells are already part of a box.
+struct Grid<'a> {
+    min: (usize, usize, usize),
+    size: (usize, usize, usize),
+    cells: Vec<Option<&'a Voxel>>,
+    claimed: Vec<u64>,
+}
+
+impl<'a> Grid<'a> {
+    fn new(voxels: &'a HashMap<(usize, usize, usize), Voxel>) -> Self {
+        let mut min = (usize::MAX, usize::MAX, usize::MAX);
+        let mut max = (usize::MIN, usize::MIN, usize::MIN);
+        for pos in voxels.keys() {
+            min.0 = min.0.min(pos.0);
+            min.1 = min.1.min(pos.1);
+            min.2 = min.2.min(pos.2);
+            max.0 = max.0.max(pos.0);
+            max.1 = max.1.max(pos.1);
+            max.2 = max.2.max(pos.2);
+        }
+        let size = if voxels.is_empty() {
+            (0, 0, 0)
+        } else {
+            (max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1)
+        };
+
+        let len = size.0 * size.1 * size.2;
+        let mut grid = Self {
+            min,
+            size,
+            cells: vec![None; len],
+            claimed: vec![0; len.div_ceil(64)],
+        };
+        for (pos, voxel) in voxels {
+            let index = grid.index((pos.0 - min.0, pos.1 - min.1, pos.2 - min.2));
+            grid.cells[index] = Some(voxel);
+        }
+        grid
+    }
+
+    // Positions are relative to `min` from here on
+    fn index(&self, pos: (usize, usize, usize)) -> usize {
+        (pos.0 * self.size.1 + pos.1) * self.size.2 + pos.2
+    }
+
+    fn is_claimed(&self, index: usize) -> bool {
+        self.claimed[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// How many cells in the (inclusive) range aren't in a box yet, or None if any of them is
+    /// empty or a different voxel.
+    fn unclaimed(
+        &self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        voxel: &Voxel,
+    ) -> Option<usize> {
+        let mut count = 0;
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let index = self.index((x, y, z));
+                    if self.cells[index] != Some(voxel) {
+                        return None;
+                    }
+                    count += !self.is_claimed(index) as usize;
+                }
+            }
+        }
+        Some(count)
+    }
+
+    /// Whether the next slab can be added to a box: all the same voxel, and none of it in a box
+    /// yet. Voxels that can overlap only need some of it to be new.
+    fn can_grow(
+        &self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        voxel: &Voxel,
+    ) -> bool {
+        let len = (max.0 - min.0 + 1) * (max.1 - min.1 + 1) * (max.2 - min.2 + 1);
+        self.unclaimed(min, max, voxel)
+            .is_some_and(|n| if voxel.overlap { n > 0 } else { n == len })
+    }
+
+    /// Moves one face of the box out a slab at a time (towards 0 if `backwards`) for as long as
+    /// `fits` accepts the next slab and it stays inside the grid.
+    fn grow(
+        &self,
+        min: &mut (usize, usize, usize),
+        max: &mut (usize, usize, usize),
+        dir: GreedyDirection,
+        backwards: bool,
+        fits: impl Fn((usize, usize, usize), (usize, usize, usize)) -> bool,
+    ) {
+        loop {
+            let next = if backwards {
+                match dir.of(*min).checked_sub(1) {
+                    Some(next) => next,
+                    None => return,
+                }
+            } else {
+                dir.of(*max) + 1
+            };
+            if next >= dir.of(self.size) {
+                return;
+            }
+
+            let (slab_min, slab_max) = (dir.with(*min, next), dir.with(*max, next));
+            if !fits(slab_min, slab_max) {
+                return;
+            }
+            if backwards {
+                *min = slab_min;
+            } else {
+                *max = slab_max;
+            }
+        }
+    }
+
+    fn to_box(&self, min: (usize, usize, usize), max: (usize, usize, usize), voxel: &Voxel) -> Box {
+        let offset =
+            |p: (usize, usize, usize)| (p.0 + self.min.0, p.1 + self.min.1, p.2 + self.min.2);
+        Box {
+            min: offset(min),
+            max: offset(max),
+            id: voxel.id.clone(),
+            properties: voxel.properties.clone(),
+        }
+    }
+
+    fn claim(&mut self, min: (usize, usize, usize), max: (usize, usize, usize)) {
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let index = self.index((x, y, z));
+                    self.claimed[index / 64] |= 1 << (index % 64);
+                }
+            }
+        }
+    }
+}
+
 pub fn greedy(
     voxels: &HashMap<(usize, usize, usize), Voxel>,
     directions: &[GreedyDirection],
 ) -> Vec<Box> {
+    let mut grid = Grid::new(voxels);
     let mut boxes: Vec<Box> = Vec::new();
 
-    let mut min = (usize::MAX, usize::MAX, usize::MAX);
-    let mut max = (usize::MIN, usize::MIN, usize::MIN);
-    for (pos, _) in voxels.iter() {
-        min.0 = min.0.min(pos.0);
-        min.1 = min.1.min(pos.1);
-        min.2 = min.2.min(pos.2);
-        max.0 = max.0.max(pos.0);
-        max.1 = max.1.max(pos.1);
-        max.2 = max.2.max(pos.2);
-    }
-
-    for x in min.0..=max.0 {
-        for y in min.1..=max.1 {
-            for z in min.2..=max.2 {
-                let voxel = voxels.get(&(x, y, z));
-                if voxel.is_none() {
+    for x in 0..grid.size.0 {
+        for y in 0..grid.size.1 {
+            for z in 0..grid.size.2 {
+                let index = grid.index((x, y, z));
+                let Some(voxel) = grid.cells[index] else {
+                    continue;
+                };
+                if grid.is_claimed(index) {
                     continue;
                 }
-                let voxel = voxel.unwrap();
 
-                if boxes.par_iter().any(|b| b.contains((x, y, z))) {
-                    continue;
+                let (mut min, mut max) = ((x, y, z), (x, y, z));
+
+                // Grow one slab at a time, until the next slab leaves the bounds, has anything
+                // that's empty or a different voxel, or is already entirely covered
+                for dir in directions.iter().filter(|d| voxel.stretches(**d)) {
+                    grid.grow(&mut min, &mut max, *dir, false, |a, b| {
+                        grid.can_grow(a, b, voxel)
+                    });
                 }
 
-                let mut new_box = Box {
-                    min: (x, y, z),
-                    max: (x, y, z),
-                    id: voxel.id.clone(),
-                    properties: voxel.properties.clone(),
-                };
+                grid.claim(min, max);
+                boxes.push(grid.to_box(min, max, voxel));
+            }
+        }
+    }
 
-                // Stop in one of the following conditions:
-                // - The next voxel is different (use Eq)
-                // - The next voxel is out of bounds
-                // - We've reached the max volume
-                // - We've hit another box
-
-                for dir in directions {
-                    match *dir {
-                        GreedyDirection::X => {
-                            while new_box.max.0 < max.0 {
-                                let next_pos = (new_box.max.0 + 1, new_box.max.1, new_box.max.2);
-                                let next_voxel = voxels.get(&next_pos);
-                                if next_voxel.is_none() || next_voxel.unwrap() != voxel {
-                                    break;
-                                }
-                                if boxes.par_iter().any(|b| b.contains(next_pos)) {
-                                    break;
-                                }
-
-                                let mut next_box = new_box.clone();
-                                next_box.max.0 += 1;
-
-                                if next_box
-                                    .all_positions()
-                                    .par_iter()
-                                    .any(|pos| voxels.get(pos).map(|v| v != voxel).unwrap_or(true))
-                                {
-                                    break;
-                                }
-
-                                new_box = next_box;
-                            }
-                        }
+    boxes
+}
 
-                        GreedyDirection::Y => {
-                            while new_box.max.1 < max.1 {
-                                let next_pos = (new_box.max.0, new_box.max.1 + 1, new_box.max.2);
-                                let next_voxel = voxels.get(&next_pos);
-                                if next_voxel.is_none() || next_voxel.unwrap() != voxel {
-                                    break;
-                                }
-                                if boxes.par_iter().any(|b| b.contains(next_pos)) {
-                                    break;
-                                }
-
-                                let mut next_box = new_box.clone();
-                                next_box.max.1 += 1;
-
-                                if next_box
-                                    .all_positions()
-                                    .par_iter()
-                                    .any(|pos| voxels.get(pos).map(|v| v != voxel).unwrap_or(true))
-                                {
-                                    break;
-                                }
-
-                                new_box = next_box;
-                            }
+/// Slower but usually tighter than `greedy`: finds the biggest box through each cell, growing
+/// both ways in every axis order, then keeps taking whichever one covers the most cells that
+/// aren't covered yet. Boxes the others ended up covering completely get dropped at the end.
+pub fn largest_first(voxels: &HashMap<(usize, usize, usize), Voxel>) -> Vec<Box> {
+    let mut grid = Grid::new(voxels);
+
+    let mut candidates = Vec::new();
+    for directions in DIRECTIONS {
+        // Cells inside an earlier box mostly grow into that same box again, skip them
+        let mut seen = vec![false; grid.cells.len()];
+        for x in 0..grid.size.0 {
+            for y in 0..grid.size.1 {
+                for z in 0..grid.size.2 {
+                    let index = grid.index((x, y, z));
+                    let Some(voxel) = grid.cells[index] else {
+                        continue;
+                    };
+                    if seen[index] {
+                        continue;
+                    }
+
+                    let (mut min, mut max) = ((x, y, z), (x, y, z));
+                    for dir in directions.into_iter().filter(|d| voxel.stretches(*d)) {
+                        for backwards in [false, true] {
+                            grid.grow(&mut min, &mut max, dir, backwards, |a, b| {
+                                grid.unclaimed(a, b, voxel).is_some()
+                            });
                         }
-                        GreedyDirection::Z => {
-                            while new_box.max.2 < max.2 {
-                                let next_pos = (new_box.max.0, new_box.max.1, new_box.max.2 + 1);
-                                let next_voxel = voxels.get(&next_pos);
-                                if next_voxel.is_none() || next_voxel.unwrap() != voxel {
-                                    break;
-                                }
-                                if boxes.par_iter().any(|b| b.contains(next_pos)) {
-                                    break;
-                                }
-
-                                let mut next_box = new_box.clone();
-                                next_box.max.2 += 1;
-
-                                if next_box
-                                    .all_positions()
-                                    .par_iter()
-                                    .any(|pos| voxels.get(pos).map(|v| v != voxel).unwrap_or(true))
-                                {
-                                    break;
-                                }
-
-                                new_box = next_box;
+                    }
+
+                    for x in min.0..=max.0 {
+                        for y in min.1..=max.1 {
+                            for z in min.2..=max.2 {
+                                seen[grid.index((x, y, z))] = true;
                             }
                         }
-                    };
+                    }
+                    candidates.push((min, max, voxel));
                 }
+            }
+        }
+    }
+    // Every cell of a box is the same voxel, so the corners are enough to tell them apart
+    candidates.sort_by_key(|(min, max, _)| (*min, *max));
+    candidates.dedup_by_key(|(min, max, _)| (*min, *max));
+
+    // Scores only ever go down as cells get covered, so a stale score at the top of the heap just
+    // needs recounting and pushing back
+    let volume = |min: (usize, usize, usize), max: (usize, usize, usize)| {
+        (max.0 - min.0 + 1) * (max.1 - min.1 + 1) * (max.2 - min.2 + 1)
+    };
+    let mut heap = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, (min, max, _))| (volume(*min, *max), i))
+        .collect::<BinaryHeap<_>>();
+    let mut chosen = Vec::new();
+    while let Some((score, i)) = heap.pop() {
+        let (min, max, voxel) = candidates[i];
+        let count = grid.unclaimed(min, max, voxel).unwrap();
+        if count == 0 {
+            continue;
+        }
+        if count < score {
+            heap.push((count, i));
+            continue;
+        }
+        grid.claim(min, max);
+        chosen.push(i);
+    }
 
-                boxes.push(new_box);
+    // Smaller boxes got picked later, so they're the likeliest to be redundant
+    let mut cover = vec![0u32; grid.cells.len()];
+    let cells = |min: (usize, usize, usize), max: (usize, usize, usize)| {
+        (min.0..=max.0).flat_map(move |x| {
+            (min.1..=max.1).flat_map(move |y| (min.2..=max.2).map(move |z| (x, y, z)))
+        })
+    };
+    for &i in &chosen {
+        let (min, max, _) = candidates[i];
+        for pos in cells(min, max) {
+            cover[grid.index(pos)] += 1;
+        }
+    }
+    let mut boxes = Vec::new();
+    for &i in chosen.iter().rev() {
+        let (min, max, voxel) = candidates[i];
+        if cells(min, max).all(|pos| cover[grid.index(pos)] > 1) {
+            for pos in cells(min, max) {
+                cover[grid.index(pos)] -= 1;
             }
+        } else {
+            boxes.push(grid.to_box(min, max, voxel));
         }
     }
 
@@ -212,18 +392,16 @@ pub fn flood(voxels: &Vec<(usize, usize, usize)>) -> Vec<Vec<(usize, usize, usiz
 
             let neighbors = [
                 (pos.0 + 1, pos.1, pos.2),
-                (pos.0 - 1, pos.1, pos.2),
+                (pos.0.wrapping_sub(1), pos.1, pos.2),
                 (pos.0, pos.1 + 1, pos.2),
-                (pos.0, pos.1 - 1, pos.2),
+                (pos.0, pos.1.wrapping_sub(1), pos.2),
                 (pos.0, pos.1, pos.2 + 1),
-                (pos.0, pos.1, pos.2 - 1),
+                (pos.0, pos.1, pos.2.wrapping_sub(1)),
             ];
 
             for neighbor in neighbors.iter() {
-                if *visited.get(neighbor).unwrap_or(&false) {
-                    continue;
-                }
-                if voxels.contains(neighbor) {
+                // Anything not in the map isn't part of the group
+                if visited.get(neighbor) == Some(&false) {
                     stack.push(*neighbor);
                 }
             }
@@ -235,26 +413,29 @@ pub fn flood(voxels: &Vec<(usize, usize, usize)>) -> Vec<Vec<(usize, usize, usiz
     clumps
 }
 
-pub fn best_greedy(voxels: &HashMap<(usize, usize, usize), Voxel>) -> Vec<Box> {
+/// Splits the voxels into connected clumps of the same block and meshes each one with every
+/// greedy axis order, keeping the fewest boxes. With `optimize`, `largest_first` gets a go at
+/// each clump too.
+pub fn best_greedy(voxels: &HashMap<(usize, usize, usize), Voxel>, optimize: bool) -> Vec<Box> {
     let mut groups = HashMap::new();
     for (pos, voxel) in voxels.iter() {
-        let group = groups
+        let (_, group) = groups
             .entry((voxel.id.clone(), voxel.properties.clone()))
-            .or_insert(Vec::new());
+            .or_insert((voxel.clone(), Vec::new()));
         group.push(*pos);
     }
 
     // Run flood fill on each group to get a list of clumps per group
     let clumps = groups
         .par_iter()
-        .flat_map(|(id, group)| {
+        .flat_map(|(_, (voxel, group))| {
             let voxels = group.to_vec();
             let clumps = flood(&voxels);
             let mut result = Vec::new();
             for clump in clumps {
                 let mut hm = HashMap::new();
                 for pos in clump.iter() {
-                    hm.insert(*pos, Voxel::new(id.0.clone(), id.1.clone()));
+                    hm.insert(*pos, voxel.clone());
                 }
                 result.push(hm);
             }
@@ -263,30 +444,256 @@ pub fn best_greedy(voxels: &HashMap<(usize, usize, usize), Voxel>) -> Vec<Box> {
         })
         .collect::<Vec<_>>();
 
-    let directions = [
-        [GreedyDirection::X, GreedyDirection::Y, GreedyDirection::Z],
-        [GreedyDirection::X, GreedyDirection::Z, GreedyDirection::Y],
-        [GreedyDirection::Y, GreedyDirection::X, GreedyDirection::Z],
-        [GreedyDirection::Y, GreedyDirection::Z, GreedyDirection::X],
-        [GreedyDirection::Z, GreedyDirection::X, GreedyDirection::Y],
-        [GreedyDirection::Z, GreedyDirection::Y, GreedyDirection::X],
-    ];
-
     println!("Running greedy on {} clumps", clumps.len());
     // Try each direction on each clump
-    clumps
+    let results = clumps
         .par_iter()
         .map(|clump| {
-            directions
+            let boxes = DIRECTIONS
                 .par_iter()
-                .map(|directions| {
-                    let boxes = greedy(clump, directions);
-                    (boxes.len(), boxes)
-                })
-                .min_by_key(|(volume, _)| *volume)
-                .unwrap()
-                .1
+                .map(|directions| greedy(clump, directions))
+                .min_by_key(|boxes| boxes.len())
+                .unwrap();
+            let baseline = boxes.len();
+            // Packing them tighter means overlapping them, so that's only for voxels that can
+            let overlap = clump.values().next().is_some_and(|voxel| voxel.overlap);
+            if !optimize || !overlap {
+                return (baseline, boxes);
+            }
+
+            let optimized = largest_first(clump);
+            if optimized.len() < baseline {
+                (baseline, optimized)
+            } else {
+                (baseline, boxes)
+            }
         })
-        .flatten()
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+
+    if optimize {
+        let baseline = results.iter().map(|(n, _)| n).sum::<usize>();
+        let optimized = results.iter().map(|(_, b)| b.len()).sum::<usize>();
+        println!(
+            "Optimized {} greedy boxes down to {} ({} fewer)",
+            baseline,
+            optimized,
+            baseline - optimized
+        );
+    }
+
+    results.into_iter().flat_map(|(_, boxes)| boxes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Pos = (usize, usize, usize);
+
+    fn cells(b: &Box) -> impl Iterator<Item = Pos> {
+        let (min, max) = (b.min, b.max);
+        (min.0..=max.0).flat_map(move |x| {
+            (min.1..=max.1).flat_map(move |y| (min.2..=max.2).map(move |z| (x, y, z)))
+        })
+    }
+
+    /// The mesher this replaced, boiled down: grow along each direction in turn while the corner
+    /// being grown into isn't in a box yet and the whole grown box is still the same voxel.
+    fn old_greedy(voxels: &HashMap<Pos, Voxel>, directions: &[GreedyDirection]) -> Vec<Box> {
+        let mut boxes: Vec<Box> = Vec::new();
+        let mut keys = voxels.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+        let max = keys
+            .iter()
+            .fold((0, 0, 0), |m, p| (m.0.max(p.0), m.1.max(p.1), m.2.max(p.2)));
+        let covered = |boxes: &[Box], pos: Pos| boxes.iter().any(|b| cells(b).any(|c| c == pos));
+
+        for pos in keys {
+            let voxel = &voxels[&pos];
+            if covered(&boxes, pos) {
+                continue;
+            }
+            let mut new_box = Box {
+                min: pos,
+                max: pos,
+                id: voxel.id.clone(),
+                properties: voxel.properties.clone(),
+            };
+            for &dir in directions {
+                while dir.of(new_box.max) < dir.of(max) {
+                    let next = dir.with(new_box.max, dir.of(new_box.max) + 1);
+                    if voxels.get(&next) != Some(voxel) || covered(&boxes, next) {
+                        break;
+                    }
+                    let mut next_box = new_box.clone();
+                    next_box.max = next;
+                    if cells(&next_box).any(|c| voxels.get(&c) != Some(voxel)) {
+                        break;
+                    }
+                    new_box = next_box;
+                }
+            }
+            boxes.push(new_box);
+        }
+        boxes
+    }
+
+    /// Whether no cell is in more than one box.
+    fn disjoint(boxes: &[Box]) -> bool {
+        let cells = boxes.iter().flat_map(cells).collect::<Vec<_>>();
+        let mut unique = cells.clone();
+        unique.sort();
+        unique.dedup();
+        unique.len() == cells.len()
+    }
+
+    fn shape(positions: impl IntoIterator<Item = Pos>, overlap: bool) -> HashMap<Pos, Voxel> {
+        positions
+            .into_iter()
+            .map(|pos| {
+                let voxel = Voxel::new("stone".to_string(), String::new()).with_overlap(overlap);
+                (pos, voxel)
+            })
+            .collect()
+    }
+
+    fn cube(size: usize) -> impl Iterator<Item = Pos> {
+        (0..size).flat_map(move |x| (0..size).flat_map(move |y| (0..size).map(move |z| (x, y, z))))
+    }
+
+    /// A handful of shapes that trip up greedy meshing one way or another, starting at 0 so
+    /// anything stepping below the grid shows up.
+    fn shapes() -> Vec<Vec<Pos>> {
+        // Steady pseudo-random noise, so the test doesn't change from run to run
+        let mut seed = 12345u32;
+        let mut noise = Vec::new();
+        for pos in cube(6) {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            if !(seed >> 16).is_multiple_of(3) {
+                noise.push(pos);
+            }
+        }
+
+        vec![
+            cube(4).collect(),
+            // Hollow room
+            cube(6)
+                .filter(|p| [p.0, p.1, p.2].iter().any(|&v| v == 0 || v == 5))
+                .collect(),
+            // Plus sign, a bar through a bar
+            (0..7)
+                .map(|x| (x, 0, 3))
+                .chain((0..7).map(|z| (3, 0, z)))
+                .collect(),
+            // Staircase
+            cube(5).filter(|p| p.1 <= p.0).collect(),
+            // L
+            cube(5).filter(|p| p.0 < 2 || p.2 < 2).collect(),
+            noise,
+        ]
+    }
+
+    #[test]
+    fn boxes_cover_exactly_the_voxels() {
+        for overlap in [false, true] {
+            for positions in shapes() {
+                let mut voxels = shape(positions, overlap);
+                // A second block mixed in, which no box may take in
+                for (pos, voxel) in voxels.iter_mut() {
+                    if (pos.0 + pos.2).is_multiple_of(4) {
+                        voxel.id = "dirt".to_string();
+                    }
+                }
+
+                let mut meshes = DIRECTIONS
+                    .iter()
+                    .map(|d| greedy(&voxels, d))
+                    .collect::<Vec<_>>();
+                if overlap {
+                    meshes.push(largest_first(&voxels));
+                }
+                for boxes in meshes {
+                    let mut count = HashMap::new();
+                    for b in &boxes {
+                        for pos in cells(b) {
+                            assert_eq!(voxels.get(&pos).map(|v| &v.id), Some(&b.id), "{pos:?}");
+                            *count.entry(pos).or_insert(0) += 1;
+                        }
+                    }
+                    assert_eq!(count.len(), voxels.len());
+                    if !overlap {
+                        assert!(count.values().all(|&n| n == 1));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_more_boxes_than_before() {
+        for positions in shapes() {
+            let what = format!("{} voxels", positions.len());
+
+            // The old mesher only checked the corner it grew into for other boxes, so it overlapped
+            // them now and then. Voxels that can overlap are the fair comparison
+            let voxels = shape(positions.clone(), true);
+            let old = DIRECTIONS
+                .iter()
+                .map(|d| old_greedy(&voxels, d))
+                .collect::<Vec<_>>();
+            let fewest = old.iter().map(|boxes| boxes.len()).min().unwrap();
+            let new = DIRECTIONS
+                .iter()
+                .map(|d| greedy(&voxels, d).len())
+                .min()
+                .unwrap();
+            assert!(new <= fewest, "{what}: {new} vs {fewest}");
+            assert!(largest_first(&voxels).len() <= fewest, "{what}");
+
+            // The ones that can't only get held to whatever the old one managed without overlaps
+            let voxels = shape(positions, false);
+            let new = DIRECTIONS
+                .iter()
+                .map(|d| greedy(&voxels, d).len())
+                .min()
+                .unwrap();
+            if let Some(fewest) = old.iter().filter(|b| disjoint(b)).map(|b| b.len()).min() {
+                assert!(new <= fewest, "{what}: {new} vs {fewest}");
+            }
+        }
+    }
+
+    #[test]
+    fn stretch_limits_growth() {
+        let voxels = cube(3)
+            .map(|pos| {
+                let voxel = Voxel::new("oak_stairs".to_string(), String::new())
+                    .with_stretch([false, false, true]);
+                (pos, voxel)
+            })
+            .collect::<HashMap<_, _>>();
+        let boxes = greedy(&voxels, &DIRECTIONS[0]);
+        assert_eq!(boxes.len(), 9);
+        assert!(boxes
+            .iter()
+            .all(|b| b.min.0 == b.max.0 && b.min.1 == b.max.1));
+    }
+
+    #[test]
+    fn flood_stops_at_zero() {
+        // Neighbours of anything on 0 used to be found by subtracting one, which overflowed
+        let voxels = vec![(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 5), (0, 0, 6)];
+        let mut clumps = flood(&voxels);
+        for clump in clumps.iter_mut() {
+            clump.sort();
+        }
+        clumps.sort();
+        assert_eq!(
+            clumps,
+            vec![
+                vec![(0, 0, 0), (0, 1, 0), (1, 0, 0)],
+                vec![(0, 0, 5), (0, 0, 6)]
+            ]
+        );
+    }
 }
@@ -0,0 +1,69 @@
+use crate::{nbt, util, Block};
+
+fn vec3(tag: &nbt::Tag, key: &str) -> anyhow::Result<(i32, i32, i32)> {
+    let tag = tag
+        .get(key)
+        .ok_or_else(|| anyhow::anyhow!("region is missing {}", key))?;
+    Ok((tag.int("x")?, tag.int("y")?, tag.int("z")?))
+}
+
+pub fn load(path: &str) -> anyhow::Result<Vec<Block>> {
+    let root = nbt::read_file(path)?;
+    let mut blocks = Vec::new();
+
+    for (name, region) in root.compound("Regions")? {
+        let position = vec3(region, "Position")?;
+        let size = vec3(region, "Size")?;
+
+        // Negative sizes mean the region extends backwards from its position
+        let origin = (
+            position.0 + (size.0 + 1).min(0),
+            position.1 + (size.1 + 1).min(0),
+            position.2 + (size.2 + 1).min(0),
+        );
+        let size = (
+            size.0.unsigned_abs() as usize,
+            size.1.unsigned_abs() as usize,
+            size.2.unsigned_abs() as usize,
+        );
+
+        let palette = region
+            .list("BlockStatePalette")?
+            .iter()
+            .map(|state| Block::from_nbt_state((0, 0, 0), state))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let states = region
+            .get("BlockStates")
+            .and_then(|t| t.as_long_array())
+            .ok_or_else(|| anyhow::anyhow!("region {} has no BlockStates", name))?;
+
+        let bits = (usize::BITS - (palette.len().max(1) - 1).leading_zeros()).max(2) as usize;
+        let volume = size.0 * size.1 * size.2;
+        if states.len() * 64 < volume * bits {
+            anyhow::bail!("region {} has truncated BlockStates", name);
+        }
+
+        for y in 0..size.1 {
+            for z in 0..size.2 {
+                for x in 0..size.0 {
+                    let index = (y * size.2 + z) * size.0 + x;
+                    let state = util::packed_value(states, bits, index);
+                    let Some(Some(block)) = palette.get(state) else {
+                        continue;
+                    };
+
+                    blocks.push(Block {
+                        pos: (
+                            origin.0 + x as i32,
+                            origin.1 + y as i32,
+                            origin.2 + z as i32,
+                        ),
+                        ..block.clone()
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(blocks)
+}
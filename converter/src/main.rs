@@ -1,296 +1,628 @@
-use image::{codecs::bmp::BmpEncoder, ImageEncoder};
+use common::{mdl, palette, transform};
 use serde::Deserialize;
-use std::{collections::HashMap, fs::File, io::BufReader};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use util::Face;
 
+mod anvil;
+mod assets;
 mod greedy;
+mod litematic;
+mod map;
+mod mapping;
+mod nbt;
+mod schem;
+mod seal;
+mod shape;
+mod source;
+mod structure;
 mod util;
+mod vmf;
+mod wad;
+
+const VISGROUP_WORLD: u32 = 1;
+const VISGROUP_ENTITIES: u32 = 2;
+const VISGROUP_LIGHTS: u32 = 3;
+const VISGROUP_MISSING: u32 = 4;
+const VISGROUP_MODELS: u32 = 5;
+/// Visgroups that get thrown away and regenerated when merging into an existing map. Missing
+/// textures are generated world brushes too, they just got sorted out for visibility.
+const GENERATED_VISGROUPS: &[u32] = &[VISGROUP_WORLD, VISGROUP_MISSING, VISGROUP_MODELS];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    GoldSrc,
+    Source,
+}
 
-const MC_TO_HAMMER: f64 = 48.;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Seal {
+    /// Sky walls around the build's outline.
+    Hull,
+    /// A sky box around everything, with this many blocks of space.
+    Box(i64),
+}
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct Block {
     pos: (i32, i32, i32),
     id: String,
     props: Option<String>,
+    /// Block entity data, for formats that carry it (chest contents, sign text...).
+    #[serde(skip)]
+    nbt: Option<nbt::Tag>,
 }
 
-fn fix_coords(pos: (f64, f64, f64)) -> (f64, f64, f64) {
-    // Offset to fit in the map and convert to Hammer units (Z up)
-    let pos = (pos.0, pos.2, pos.1);
-    let offset = (0., -(4096. - 256.), -512.);
-    (pos.0 + offset.0, pos.1 + offset.1, pos.2 + offset.2)
-}
+impl Block {
+    /// Builds a block from a namespaced state (`minecraft:oak_log` + `{axis: y}`), matching what
+    /// exporter.py used to produce. Air gives `None`.
+    fn from_state<'a>(
+        pos: (i32, i32, i32),
+        name: &str,
+        props: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Option<Self> {
+        if matches!(
+            name,
+            "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"
+        ) {
+            return None;
+        }
 
-fn build_brush(
-    id: &mut usize,
-    r#box: &greedy::Box,
-    voxels: &HashMap<(usize, usize, usize), greedy::Voxel>,
-    textures: &[String],
-    missing_textures: &mut Vec<String>,
-) -> String {
-    let mut brush = format!(
-        r#"
-  solid
-  {{
-    "id" "{}"
-"#,
-        id
-    );
-    *id += 1;
+        let id = name.split_once(':').map(|(_, id)| id).unwrap_or(name);
+        let props = props
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>();
+        let props = if props.is_empty() {
+            "".to_string()
+        } else {
+            format!("[{}]", props.join(","))
+        };
 
-    // They're always cubes, build six sides ez
-    let min = (
-        r#box.min.0 as f64 * MC_TO_HAMMER,
-        r#box.min.1 as f64 * MC_TO_HAMMER,
-        r#box.min.2 as f64 * MC_TO_HAMMER,
-    );
-    let max = (
-        // Because we're scaling cubes to brushes, add 1 to the max
-        (r#box.max.0 + 1) as f64 * MC_TO_HAMMER,
-        (r#box.max.1 + 1) as f64 * MC_TO_HAMMER,
-        (r#box.max.2 + 1) as f64 * MC_TO_HAMMER,
-    );
+        Some(Self {
+            pos,
+            id: id.to_string(),
+            props: Some(props),
+            nbt: None,
+        })
+    }
 
-    let min = fix_coords(min);
-    let mut max = fix_coords(max);
-    let mut missing_any = false;
+    /// Same as `from_state`, but for full state strings like `minecraft:oak_log[axis=y]`.
+    fn from_state_string(pos: (i32, i32, i32), state: &str) -> Option<Self> {
+        let state = state.trim().to_lowercase();
+        let (name, props) = match state.split_once('[') {
+            Some((name, props)) => (name, util::parse_properties(props)),
+            None => (state.as_str(), HashMap::new()),
+        };
+        // Sort so the same state always groups together in the greedy mesher
+        let props = props.into_iter().collect::<BTreeMap<_, _>>();
+        let name = if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("minecraft:{}", name)
+        };
 
-    // Dirty hack: shrink path blocks
-    if r#box.id == "dirt_path" {
-        max.2 -= MC_TO_HAMMER / 16.;
+        Self::from_state(pos, &name, props.iter().map(|(k, v)| (k.trim(), v.trim())))
     }
 
-    let props = util::parse_properties(&r#box.properties);
-    let face = match props.get("facing").map(|s| s.as_str()).unwrap_or("north") {
-        "north" => Face::North,
-        "east" => Face::East,
-        "south" => Face::South,
-        "west" => Face::West,
-        "up" => Face::Top,
-        "down" => Face::Bottom,
-        _ => unreachable!(),
-    };
-
-    for side_id in 0..6 {
-        // I'm gonna let copilot write this one. Here's some VDC quotes:
-
-        // The plane is defined using these three points. The first marks the bottom left of the face, the second marks the top left, and the third marks the top right.
-        // "plane" "(256 -256 160) (256 -256 -0) (256 256 160)"
-        // "plane" "(288 256 160) (288 256 -0) (288 -256 160)"
-        // "plane" "(256 256 160) (256 256 -0) (288 256 160)"
-        // "plane" "(288 -256 160) (288 -256 -0) (256 -256 160)"
-        // "plane" "(256 256 0) (256 -256 0) (288 256 0)"
-        // "plane" "(256 -256 160) (256 256 160) (288 -256 160)"
-
-        // TODO: adjust texture based on face
-        let texture = match r#box.id.as_str() {
-            "tinted_glass" => "SKY".to_string(),
-            "barrier" => "CLIP".to_string(),
-            "light_blue_concrete" => "lbconc".to_string(),
-            "chiseled_polished_blackstone" => "cpbs".to_string(),
-            "cracked_polished_blackstone_bricks" => "cpbsb".to_string(),
-            "gilded_blackstone" => "gbs".to_string(),
-            "polished_blackstone" => "pbs".to_string(),
-            "polished_blackstone_bricks" => "pbsb".to_string(),
-            "stripped_spruce_log" => "ssl".to_string(),
-            "white_terracotta" => "wt".to_string(),
-            "light_gray_terracotta" => "lgt".to_string(),
-            "light_blue_terracotta" => "lbt".to_string(),
-            "chiseled_stone_bricks" => "csb".to_string(),
-
-            "glass" => "{glass".to_string(),
-            "glass_pane" => "{glass".to_string(),
-            "blue_stained_glass" => "{bsg".to_string(),
-            "oak_leaves" => "{oak_leaves".to_string(),
-            "spruce_leaves" => "{spruce_leaves".to_string(),
-
-            "grass_block" => {
-                if side_id == Face::Top as usize {
-                    "grass_block".to_string()
-                } else if side_id == Face::Bottom as usize {
-                    "dirt".to_string()
-                } else {
-                    "gbs2".to_string()
-                }
-            }
+    /// Same as `from_state`, but for palette compounds with `Name` and `Properties`.
+    fn from_nbt_state(pos: (i32, i32, i32), state: &nbt::Tag) -> anyhow::Result<Option<Self>> {
+        let name = state.string("Name")?;
+        let props = state
+            .get("Properties")
+            .and_then(|p| p.as_compound())
+            .into_iter()
+            .flatten()
+            .filter_map(|(k, v)| Some((k.as_str(), v.as_str()?)));
+        Ok(Self::from_state(pos, name, props))
+    }
 
-            "crafting_table" => {
-                if side_id == 5 {
-                    "ct_top".to_string()
-                } else if side_id == face as usize {
-                    "ct_front".to_string()
-                } else {
-                    "ct_side".to_string()
-                }
-            }
+    /// What a sign says, lines joined with slashes, if its block entity data came along.
+    fn sign_text(&self) -> Option<String> {
+        let nbt = self.nbt.as_ref()?;
+        // 1.20 moved the lines into front_text, before that they were Text1 to Text4
+        let lines = match nbt.get("front_text") {
+            Some(front) => front
+                .list("messages")
+                .ok()?
+                .iter()
+                .filter_map(|m| m.as_str())
+                .collect::<Vec<_>>(),
+            None => (1..=4)
+                .filter_map(|i| nbt.get(&format!("Text{}", i))?.as_str())
+                .collect(),
+        };
+        let lines = lines
+            .into_iter()
+            .map(component_text)
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>();
+        (!lines.is_empty()).then(|| lines.join(" / "))
+    }
+}
 
-            "furnace" => {
-                if side_id == 5 {
-                    "furnace_top".to_string()
-                } else if side_id == face as usize {
-                    "furnace_front".to_string()
-                } else {
-                    "furnace_side".to_string()
-                }
-            }
-            "blast_furnace" => {
-                if side_id == 5 {
-                    "bf_top".to_string()
-                } else if side_id == face as usize {
-                    "bf_front".to_string()
-                } else {
-                    "bf_side".to_string()
+/// The plain text of a JSON text component, or the string itself if it isn't one.
+fn component_text(json: &str) -> String {
+    fn walk(value: &serde_json::Value, out: &mut String) {
+        match value {
+            serde_json::Value::String(s) => out.push_str(s),
+            serde_json::Value::Array(parts) => parts.iter().for_each(|p| walk(p, out)),
+            serde_json::Value::Object(component) => {
+                if let Some(text) = component.get("text") {
+                    walk(text, out);
                 }
-            }
-            "smoker" => {
-                if side_id == Face::Top as usize {
-                    "smoker_top".to_string()
-                } else if side_id == Face::Bottom as usize {
-                    "smoker_bottom".to_string()
-                } else if side_id == face as usize {
-                    "smoker_front".to_string()
-                } else {
-                    "smoker_side".to_string()
+                if let Some(extra) = component.get("extra") {
+                    walk(extra, out);
                 }
             }
+            _ => {}
+        }
+    }
 
-            "barrel" => {
-                // TODO
-                if side_id == Face::Top as usize {
-                    "barrel_top".to_string()
-                } else if side_id == Face::Bottom as usize {
-                    "barrel_bottom".to_string()
-                } else {
-                    "barrel_side".to_string()
-                }
-            }
+    match serde_json::from_str(json) {
+        Ok(value) => {
+            let mut out = String::new();
+            walk(&value, &mut out);
+            out
+        }
+        Err(_) => json.to_string(),
+    }
+}
 
-            "oak_log" => {
-                if side_id == Face::Top as usize || side_id == Face::Bottom as usize {
-                    "oak_log_top".to_string()
-                } else {
-                    "oak_log".to_string()
-                }
-            }
-            "birch_log" => {
-                if side_id == Face::Top as usize || side_id == Face::Bottom as usize {
-                    "birch_log_top".to_string()
-                } else {
-                    "birch_log".to_string()
+fn load_blocks(args: &[String]) -> anyhow::Result<Vec<Block>> {
+    let path = args
+        .first()
+        .map(|s| s.as_str())
+        .unwrap_or("jaybirthday.json");
+
+    // A world save plus a bounding box: converter <world> <x1> <y1> <z1> <x2> <y2> <z2>
+    if std::path::Path::new(path).is_dir() {
+        let coords = args[1..]
+            .iter()
+            .map(|s| s.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x1, y1, z1, x2, y2, z2] = coords[..] else {
+            anyhow::bail!("reading a world needs min and max block coordinates");
+        };
+        return anvil::load(path, (x1, y1, z1), (x2, y2, z2));
+    }
+
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+
+    match extension {
+        "litematic" => litematic::load(path),
+        "schem" => schem::load(path),
+        // Legacy MCEdit schematics store numeric block ids, nothing like Sponge
+        "schematic" => anyhow::bail!(
+            "{} looks like an MCEdit .schematic, which isn't supported; save it as a Sponge .schem",
+            path
+        ),
+        // Optional palette index for structures that have several
+        "nbt" => structure::load(
+            path,
+            args.get(1).map(|s| s.parse()).transpose()?.unwrap_or(0),
+        ),
+        // Old exporter.py output
+        _ => Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+    }
+}
+
+/// Pulls `name <value>` out of the arguments, so the positional ones stay in order.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|a| a == name)?;
+    args.remove(index);
+    (index < args.len()).then(|| args.remove(index))
+}
+
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let index = args.iter().position(|a| a == name);
+    index.map(|i| args.remove(i)).is_some()
+}
+
+/// The three plane points for one side of an axis-aligned box, in Hammer's winding.
+fn box_plane(face: Face, min: vmf::Vec3, max: vmf::Vec3) -> [vmf::Vec3; 3] {
+    match face {
+        Face::South => [
+            (max.0, min.1, max.2),
+            (max.0, min.1, min.2),
+            (min.0, min.1, max.2),
+        ],
+        Face::East => [
+            (max.0, max.1, max.2),
+            (max.0, max.1, min.2),
+            (max.0, min.1, max.2),
+        ],
+        Face::North => [
+            (min.0, max.1, max.2),
+            (min.0, max.1, min.2),
+            (max.0, max.1, max.2),
+        ],
+        Face::West => [
+            (min.0, min.1, max.2),
+            (min.0, min.1, min.2),
+            (min.0, max.1, max.2),
+        ],
+        Face::Bottom => [
+            (min.0, max.1, min.2),
+            (min.0, min.1, min.2),
+            (max.0, max.1, min.2),
+        ],
+        Face::Top => [
+            (min.0, min.1, max.2),
+            (min.0, max.1, max.2),
+            (max.0, min.1, max.2),
+        ],
+    }
+}
+
+/// A box with one tool texture all over, between two corners in blocks.
+fn tool_brush(
+    min: vmf::Vec3,
+    max: vmf::Vec3,
+    texture: &str,
+    target: Target,
+    transform: &transform::Transform,
+) -> vmf::Solid {
+    let (min, max) = transform.bounds(min, max);
+    let material = match target {
+        Target::GoldSrc => texture.to_string(),
+        Target::Source => source::material(texture),
+    };
+
+    vmf::Solid {
+        sides: Face::ALL
+            .map(|face| {
+                let (uaxis, vaxis) = face.texture_axes();
+                vmf::Side {
+                    plane: box_plane(face, min, max),
+                    material: material.clone(),
+                    uaxis: vmf::TextureAxis {
+                        axis: uaxis,
+                        offset: 0.,
+                        scale: 1.,
+                    },
+                    vaxis: vmf::TextureAxis {
+                        axis: vaxis,
+                        offset: 0.,
+                        scale: 1.,
+                    },
+                    rotation: 0.,
+                    lightmap_scale: 0,
+                    smoothing_groups: 0,
+                    extra: Vec::new(),
                 }
+            })
+            .to_vec(),
+        editor: vmf::Editor::new(VISGROUP_WORLD),
+        extra: Vec::new(),
+    }
+}
+
+/// func_door_rotating's spawnflags for turning around `axis`, a block direction (x east, y up,
+/// z south): which of Hammer's axes it's on, and whether it has to go in reverse.
+fn hinge_flags(axis: [f64; 3], transform: &transform::Transform) -> u32 {
+    // Pick whichever of Hammer's axes the hinge ended up on, and which way around it opens
+    let axis = transform.direction((axis[0], -axis[2], axis[1]));
+    let (along, backwards) = if axis.0 != 0. {
+        (64, axis.0 < 0.)
+    } else if axis.1 != 0. {
+        (128, axis.1 < 0.)
+    } else {
+        (0, axis.2 < 0.)
+    };
+    if backwards != transform.mirrored() {
+        along | 2
+    } else {
+        along
+    }
+}
+
+/// Whether a block hides the faces of whatever's next to it. Glass and leaves end up as see-through
+/// entities, and masked, clip and shaped blocks don't cover the whole face either.
+fn is_opaque(voxel: &greedy::Voxel, mapping: &mapping::TextureMap) -> bool {
+    if (voxel.id.contains("glass") && !voxel.id.contains("tinted")) || voxel.id.contains("leaves") {
+        return false;
+    }
+
+    let props = util::parse_properties(&voxel.properties);
+    if shape::parts(&voxel.id, &props).is_some() {
+        return false;
+    }
+    !mapping
+        .textures(&voxel.id, &props)
+        .faces
+        .iter()
+        .flatten()
+        .any(|t| t.starts_with('{') || t == "CLIP")
+}
+
+/// Whether a block's brushes end up in the world rather than in an entity of their own.
+fn is_world_brush(id: &str) -> bool {
+    !(shape::is_pane(id)
+        || (id.contains("glass") && !id.contains("tinted"))
+        || id.contains("leaves"))
+}
+
+/// Every block a brush fills completely. Thin or slanted brushes only count where they cover a
+/// whole block, anything less could still leave a gap hlbsp leaks through.
+fn solid_cells(solid: &vmf::Solid, transform: &transform::Transform) -> Vec<seal::Cell> {
+    let points = solid
+        .sides
+        .iter()
+        .flat_map(|side| side.plane)
+        .map(|point| transform.block(point))
+        .collect::<Vec<_>>();
+    if points.is_empty() || solid.sides.iter().all(|side| side.material == "CLIP") {
+        return Vec::new();
+    }
+
+    // Outward normals, checked against the middle of the brush since a mirrored transform turns
+    // the winding around
+    let count = points.len() as f64;
+    let center = points.iter().fold((0., 0., 0.), |c, p| {
+        (c.0 + p.0 / count, c.1 + p.1 / count, c.2 + p.2 / count)
+    });
+    let planes = solid
+        .sides
+        .iter()
+        .map(|side| {
+            let [a, b, c] = side.plane.map(|p| transform.block(p));
+            let normal = map::cross(map::sub(b, a), map::sub(c, a));
+            if map::dot(normal, map::sub(center, a)) > 0. {
+                (a, (-normal.0, -normal.1, -normal.2))
+            } else {
+                (a, normal)
             }
-            "stripped_dark_oak_log" => {
-                if side_id == Face::Top as usize || side_id == Face::Bottom as usize {
-                    "sdolt".to_string()
-                } else {
-                    "sdol".to_string()
+        })
+        .collect::<Vec<_>>();
+    let inside = |p: vmf::Vec3| {
+        planes.iter().all(|(a, normal)| {
+            let len = map::dot(*normal, *normal).sqrt();
+            len == 0. || map::dot(*normal, map::sub(p, *a)) / len <= 0.001
+        })
+    };
+
+    let range = |get: fn(&vmf::Vec3) -> f64| {
+        let min = points.iter().map(get).fold(f64::MAX, f64::min);
+        let max = points.iter().map(get).fold(f64::MIN, f64::max);
+        min.floor() as i64..max.ceil() as i64
+    };
+    let (xs, ys, zs) = (range(|p| p.0), range(|p| p.1), range(|p| p.2));
+    let mut cells = Vec::new();
+    for x in xs {
+        for y in ys.clone() {
+            for z in zs.clone() {
+                let corners = (0..8).map(|i| {
+                    (
+                        (x + (i & 1)) as f64,
+                        (y + (i >> 1 & 1)) as f64,
+                        (z + (i >> 2 & 1)) as f64,
+                    )
+                });
+                if corners.into_iter().all(inside) {
+                    cells.push((x, y, z));
                 }
             }
+        }
+    }
+    cells
+}
 
-            _ => {
-                if textures.iter().any(|t| *t == format!("{}.png", r#box.id)) {
-                    r#box.id.clone()
-                } else {
-                    if !missing_textures.iter().any(|t| *t == r#box.id) {
-                        missing_textures.push(r#box.id.clone());
-                    }
-                    missing_any = true;
-                    "MISSING".to_string()
-                }
+/// Entities hlbsp would complain about leaking, with the block each one's in.
+fn leak_targets(
+    vmf: &vmf::Vmf,
+    transform: &transform::Transform,
+) -> Vec<(String, vmf::Vec3, seal::Cell)> {
+    vmf.entities
+        .iter()
+        .filter_map(|entity| {
+            // The compilers drop info_null before hlbsp ever sees it
+            if entity.classname == "info_null" {
+                return None;
             }
-        };
+            let (_, origin) = entity.properties.iter().find(|(k, _)| k == "origin")?;
+            let origin = vmf::parse_vec3(origin).ok()?;
+            let block = transform.block(origin);
+            let cell = (
+                block.0.floor() as i64,
+                block.1.floor() as i64,
+                block.2.floor() as i64,
+            );
+            Some((entity.classname.clone(), origin, cell))
+        })
+        .collect()
+}
 
-        let plane_one = match side_id {
-            0 => (min.0, min.1, max.2),
-            1 => (max.0, max.1, max.2),
-            2 => (min.0, max.1, max.2),
-            3 => (max.0, min.1, max.2),
-            4 => (min.0, max.1, min.2),
-            5 => (min.0, min.1, max.2),
-            _ => unreachable!(),
-        };
-        let plane_two = match side_id {
-            0 => (min.0, min.1, min.2),
-            1 => (max.0, max.1, min.2),
-            2 => (min.0, max.1, min.2),
-            3 => (max.0, min.1, min.2),
-            4 => (min.0, min.1, min.2),
-            5 => (min.0, max.1, max.2),
-            _ => unreachable!(),
-        };
-        let plane_three = match side_id {
-            0 => (min.0, max.1, max.2),
-            1 => (max.0, min.1, max.2),
-            2 => (max.0, max.1, max.2),
-            3 => (min.0, min.1, max.2),
-            4 => (max.0, max.1, min.2),
-            5 => (max.0, min.1, max.2),
-            _ => unreachable!(),
-        };
+/// Whether every block across `face` of the box is opaque, so nothing can ever see that side.
+fn is_hidden(r#box: &greedy::Box, face: Face, opaque: &HashSet<(usize, usize, usize)>) -> bool {
+    let (min, max) = (r#box.min, r#box.max);
+    // Voxel coordinates are Minecraft's, so north is -Z. Wrapping at zero lands outside the map,
+    // which counts as open.
+    let (min, max) = match face {
+        Face::East => ((max.0 + 1, min.1, min.2), (max.0 + 1, max.1, max.2)),
+        Face::West => (
+            (min.0.wrapping_sub(1), min.1, min.2),
+            (min.0.wrapping_sub(1), max.1, max.2),
+        ),
+        Face::North => (
+            (min.0, min.1, min.2.wrapping_sub(1)),
+            (max.0, max.1, min.2.wrapping_sub(1)),
+        ),
+        Face::South => ((min.0, min.1, max.2 + 1), (max.0, max.1, max.2 + 1)),
+        Face::Top => ((min.0, max.1 + 1, min.2), (max.0, max.1 + 1, max.2)),
+        Face::Bottom => (
+            (min.0, min.1.wrapping_sub(1), min.2),
+            (max.0, min.1.wrapping_sub(1), max.2),
+        ),
+    };
 
-        let uv_scale = 48. / 16.;
-        // Side faces only
-        let v_offset = if side_id == 4 || side_id == 5 {
-            0.
-        } else {
-            5.35
-        };
+    (min.0..=max.0)
+        .all(|x| (min.1..=max.1).all(|y| (min.2..=max.2).all(|z| opaque.contains(&(x, y, z)))))
+}
 
-        let side = format!(
-            r#"
-    side
-    {{
-      "id" "{}"
-      "plane" "({} {} {}) ({} {} {}) ({} {} {})"
-      "material" "{}"
-      "uaxis" "[1 0 0 0] {}"
-      "vaxis" "[0 -1 0 {}] {}"
-      "rotation" "0"
-      "lightmapscale" "0"
-      "smoothing_groups" "0"
-    }}
-"#,
-            id,
-            plane_one.0,
-            plane_one.1,
-            plane_one.2,
-            //
-            plane_two.0,
-            plane_two.1,
-            plane_two.2,
-            //
-            plane_three.0,
-            plane_three.1,
-            plane_three.2,
-            //
-            texture,
-            uv_scale,
-            v_offset,
-            uv_scale
+#[allow(clippy::too_many_arguments)]
+fn build_brushes(
+    r#box: &greedy::Box,
+    mapping: &mapping::TextureMap,
+    textures: &[String],
+    missing_textures: &mut Vec<String>,
+    opaque: &HashSet<(usize, usize, usize)>,
+    hidden_texture: &str,
+    target: Target,
+    transform: &transform::Transform,
+) -> Vec<vmf::Solid> {
+    let props = util::parse_properties(&r#box.properties);
+    let block = mapping.textures(&r#box.id, &props);
+
+    // Slabs and stairs usually don't have textures of their own, so look for what they're made of
+    let has_texture = |id: &str, faces: &[Option<String>; 6]| {
+        faces.iter().any(|f| f.is_some()) || textures.contains(&format!("{}.png", id))
+    };
+    let fallback = if has_texture(&r#box.id, &block.faces) {
+        None
+    } else {
+        shape::texture_sources(&r#box.id, &props)
+            .into_iter()
+            .find_map(|source| {
+                let faces = mapping.textures(&source, &HashMap::new()).faces;
+                has_texture(&source, &faces).then_some((source, faces))
+            })
+    };
+
+    // Textures are 16 pixels a block, and lined up with the block grid wherever it ends up
+    let uv_scale = transform.scale / 16.;
+    let grid_origin = transform.point((0., 0., 0.));
+    let block_aligned = |axis: vmf::Vec3| {
+        let along = grid_origin.0 * axis.0 + grid_origin.1 * axis.1 + grid_origin.2 * axis.2;
+        // Adding zero turns -0 into 0 for the output
+        (-along / uv_scale).rem_euclid(16.) + 0.
+    };
+
+    // Full blocks are one part covering the whole thing
+    let parts = shape::parts(&r#box.id, &props).unwrap_or(vec![([0.; 3], [1.; 3])]);
+    let mut solids = Vec::new();
+    for (from, to) in parts {
+        let mut sides = Vec::new();
+        let mut missing_any = false;
+
+        // Parts are fractions of a block, stretched over the whole box. Because we're scaling
+        // cubes to brushes, max is one block further than the last voxel
+        let min = (
+            r#box.min.0 as f64 + from[0],
+            r#box.min.1 as f64 + from[1],
+            r#box.min.2 as f64 + from[2],
+        );
+        let max = (
+            r#box.max.0 as f64 + to[0],
+            r#box.max.1 as f64 + to[1],
+            r#box.max.2 as f64 + to[2],
         );
-        *id += 1;
-        brush = brush + &side;
-    }
-
-    brush += format!(
-        r#"
-    editor
-    {{
-      "color" "0 255 0"
-      "visgroupid" "{}"
-      "visgroupshown" "1"
-    }}
-  }}"#,
-        if missing_any { "4" } else { "1" }
-    )
-    .as_str();
-
-    brush
+        let (min, max) = transform.bounds(min, max);
+
+        for face in Face::ALL {
+            // I'm gonna let copilot write this one. Here's some VDC quotes:
+
+            // The plane is defined using these three points. The first marks the bottom left of the face, the second marks the top left, and the third marks the top right.
+            // "plane" "(256 -256 160) (256 -256 -0) (256 256 160)"
+            // "plane" "(288 256 160) (288 256 -0) (288 -256 160)"
+            // "plane" "(256 256 160) (256 256 -0) (288 256 160)"
+            // "plane" "(288 -256 160) (288 -256 -0) (256 -256 160)"
+            // "plane" "(256 256 0) (256 -256 0) (288 256 0)"
+            // "plane" "(256 -256 160) (256 256 160) (288 -256 160)"
+
+            // Only sides of the part that lie on the edge of the block can be buried
+            let on_edge = match face {
+                Face::East => to[0] == 1.,
+                Face::West => from[0] == 0.,
+                Face::Top => to[1] == 1.,
+                Face::Bottom => from[1] == 0.,
+                Face::South => to[2] == 1.,
+                Face::North => from[2] == 0.,
+            };
+
+            // Which face of the unrotated block ends up here decides the texture and its axes
+            let source_face = block.rotation.source_face(face);
+            let texture = match &block.faces[source_face as usize] {
+                _ if on_edge && is_hidden(r#box, face, opaque) => hidden_texture.to_string(),
+                Some(texture) => texture.clone(),
+                None if textures.contains(&format!("{}.png", r#box.id)) => r#box.id.clone(),
+                None => match &fallback {
+                    Some((_, faces)) if faces[source_face as usize].is_some() => {
+                        faces[source_face as usize].clone().unwrap()
+                    }
+                    Some((source, _)) if textures.contains(&format!("{}.png", source)) => {
+                        source.clone()
+                    }
+                    _ => {
+                        if !missing_textures.contains(&r#box.id) {
+                            missing_textures.push(r#box.id.clone());
+                        }
+                        missing_any = true;
+                        "MISSING".to_string()
+                    }
+                },
+            };
+
+            // Faces are worked out facing their way in the world, the transform decides where that is
+            let plane = box_plane(
+                Face::from_normal(transform.direction(face.normal())),
+                min,
+                max,
+            );
+
+            let (uaxis, vaxis) = source_face.texture_axes();
+            let (uaxis, vaxis) = (
+                transform.direction(block.rotation.vector(uaxis)),
+                transform.direction(block.rotation.vector(vaxis)),
+            );
+
+            let material = match target {
+                Target::GoldSrc => texture,
+                Target::Source => source::material(&texture),
+            };
+
+            sides.push(vmf::Side {
+                plane,
+                material,
+                uaxis: vmf::TextureAxis {
+                    axis: uaxis,
+                    offset: block_aligned(uaxis),
+                    scale: uv_scale,
+                },
+                vaxis: vmf::TextureAxis {
+                    axis: vaxis,
+                    offset: block_aligned(vaxis),
+                    scale: uv_scale,
+                },
+                rotation: 0.,
+                lightmap_scale: 0,
+                smoothing_groups: 0,
+                extra: Vec::new(),
+            });
+        }
+
+        solids.push(vmf::Solid {
+            sides,
+            editor: vmf::Editor::new(if missing_any {
+                VISGROUP_MISSING
+            } else {
+                VISGROUP_WORLD
+            }),
+            extra: Vec::new(),
+        });
+    }
+    solids
 }
 
-fn convert_obj_to_smd(model_name: String, exported_models: &mut [String]) -> anyhow::Result<()> {
-    if exported_models.iter().any(|m| *m == model_name) {
+fn convert_obj(
+    model_name: String,
+    exported_models: &mut [String],
+    dither: bool,
+    scale: f64,
+) -> anyhow::Result<()> {
+    if exported_models.contains(&model_name) {
         return Ok(());
     }
 
@@ -314,7 +646,13 @@ end
 triangles
 "#
     .to_string();
-    let mut material_files = Vec::new();
+    let mut mdl = mdl::Model {
+        name: model_name.clone(),
+        // No $origin in the QC, so studiomdl would turn it 90 degrees
+        rotation: 90.,
+        textures: Vec::new(),
+        meshes: Vec::new(),
+    };
 
     for model in models {
         let mesh = model.mesh;
@@ -322,20 +660,26 @@ triangles
         // Extract the diffuse texture to a bmp
         let diffuse_name = material.diffuse_texture.clone().unwrap();
         let bmp_name = diffuse_name.replace(".png", ".bmp");
-        if !material_files.iter().any(|m| *m == bmp_name) {
-            material_files.push(bmp_name.clone());
-        }
-
-        let image_converter = "W:/bullshit/projects/jaybirthday/ImageConverter/ImageConverter/bin/Debug/net8.0-windows/ImageConverter.exe";
-        std::process::Command::new(image_converter)
-            .arg(format!("../models/{}", diffuse_name))
-            .current_dir("../models")
-            .stdout(std::process::Stdio::piped())
-            .spawn()?
-            .wait_with_output()?;
+        let texture = match mdl.textures.iter().position(|t| t.name == bmp_name) {
+            Some(texture) => texture,
+            None => {
+                // Models are always masked, so 255 stays free for the transparent bits
+                let image = image::open(format!("../models/{}", diffuse_name))?.to_rgba8();
+                let image = palette::quantize(&image, true, dither);
+                image.write_bmp(format!("../models_out/{}", bmp_name))?;
+                mdl.textures.push(mdl::Texture {
+                    name: bmp_name.clone(),
+                    image,
+                    masked: true,
+                });
+                mdl.textures.len() - 1
+            }
+        };
+        let mut triangles = Vec::new();
 
         for idx in 0..mesh.indices.len() / 3 {
-            let mut face = format!("{}\n", diffuse_name.replace(".png", ".bmp"));
+            let mut face = format!("{}\n", bmp_name);
+            let mut triangle = Vec::new();
             for vtx in 0..3 {
                 let mut pos = (
                     mesh.positions[(mesh.indices[(idx * 3) + vtx] * 3) as usize],
@@ -343,9 +687,9 @@ triangles
                     mesh.positions[(mesh.indices[(idx * 3) + vtx] * 3 + 2) as usize],
                 );
                 // HACK
-                pos.0 *= MC_TO_HAMMER as f32;
-                pos.1 *= MC_TO_HAMMER as f32;
-                pos.2 *= MC_TO_HAMMER as f32;
+                pos.0 *= scale as f32;
+                pos.1 *= scale as f32;
+                pos.2 *= scale as f32;
 
                 let normal = (
                     mesh.normals[(mesh.normal_indices[(idx * 3) + vtx] * 3) as usize],
@@ -361,12 +705,21 @@ triangles
                     "0 {} {} {} {} {} {} {} {}\n",
                     pos.0, pos.1, pos.2, normal.0, normal.1, normal.2, uv.0, uv.1
                 );
+                triangle.push(mdl::Vertex {
+                    pos: [pos.0, pos.1, pos.2],
+                    normal: [normal.0, normal.1, normal.2],
+                    uv: [uv.0, uv.1],
+                });
             }
             smd += &face;
+            triangles.push([triangle[0], triangle[1], triangle[2]]);
         }
+
+        mdl.meshes.push(mdl::Mesh { texture, triangles });
     }
 
     smd += "end\n";
+    // The SMD and QC are still written in case anyone wants to run them through studiomdl
     std::fs::write(format!("../models_out/{}.smd", model_name), smd)?;
     let mut qc = format!(
         r#"$modelname "{}.mdl"
@@ -376,8 +729,8 @@ $body "studio" "{}"
         model_name, model_name
     );
 
-    for material in material_files {
-        qc += &format!("$texrendermode \"{}\" masked\n", material);
+    for texture in &mdl.textures {
+        qc += &format!("$texrendermode \"{}\" masked\n", texture.name);
     }
     qc += &format!(
         r#"$sequence "idle" {{
@@ -389,91 +742,101 @@ $body "studio" "{}"
     );
 
     std::fs::write(format!("../models_out/{}.qc", model_name), qc)?;
-
-    let studiomdl = "W:/bullshit/projects/jaybirthday/studiomdl.exe";
-    std::process::Command::new(studiomdl)
-        .arg(format!("./{}.qc", model_name))
-        .current_dir("../models_out")
-        .stdout(std::process::Stdio::piped())
-        .spawn()?
-        .wait_with_output()?;
-
-    let models = "E:/SteamLibrary/steamapps/common/Half-Life/birthday/models";
-    std::fs::copy(
-        format!("../models_out/{}.mdl", model_name),
-        format!("{}/{}.mdl", models, model_name),
-    )?;
+    std::fs::write(format!("../models_out/{}.mdl", model_name), mdl.write()?)?;
     Ok(())
 }
 
 fn build_model(
-    id: &mut usize,
     pos: (usize, usize, usize),
     voxel: &greedy::Voxel,
     exported_models: &mut Vec<String>,
-) -> Option<String> {
-    let pos = (
-        (pos.0 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-        (pos.1 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-        (pos.2 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-    );
-    let pos = fix_coords(pos);
+    dither: bool,
+    transform: &transform::Transform,
+) -> anyhow::Result<Option<vmf::Entity>> {
+    let pos = transform.point((pos.0 as f64 + 0.5, pos.1 as f64 + 0.5, pos.2 as f64 + 0.5));
+    // Models can only turn around the vertical to follow the map, not get mirrored
+    let east = transform.direction((1., 0., 0.));
+    let yaw = east.1.atan2(east.0).to_degrees() + 0.;
 
     let props = util::parse_properties(&voxel.properties);
     let model = match voxel.id.as_str() {
-        "campfire" => Some("campfire"),
-        "grass" => Some("grass"),
+        "campfire" => "campfire",
+        "grass" => "grass",
         "fire" => {
             if props
                 .values()
                 .filter(|x| **x == "true" || **x == "false")
                 .all(|x| x == "false")
             {
-                Some("fire_floor")
+                "fire_floor"
             } else {
-                None
+                return Ok(None);
             }
         }
-        v if v.starts_with("potted_") => Some("flower_pot"),
-        _ => Some(voxel.id.as_str()),
-    }?;
+        v if v.starts_with("potted_") => "flower_pot",
+        _ => voxel.id.as_str(),
+    };
 
     if !std::path::Path::new(&format!("../models/{}.obj", model)).exists() {
-        return None;
+        return Ok(None);
     }
 
     if !exported_models.iter().any(|m| *m == model) {
-        convert_obj_to_smd(model.to_string(), exported_models).unwrap();
+        convert_obj(model.to_string(), exported_models, dither, transform.scale)?;
         exported_models.push(model.to_string());
     }
 
-    let entity = format!(
-        r#"
-entity
-{{
-  "id" "{}"
-  "classname" "env_sprite"
-  "origin" "{} {} {}"
-  "model" "models/{}.mdl"
-  "angles" "-90 0 0"
-  editor
-  {{
-    "visgroupid" "5"
-  }}
-}}
-"#,
-        id, pos.0, pos.1, pos.2, model
-    );
-    *id += 1;
-    Some(entity)
+    Ok(Some(
+        vmf::Entity::new("env_sprite")
+            .with_origin(pos)
+            .with("model", format!("models/{}.mdl", model))
+            .with("angles", format!("-90 {} 0", yaw))
+            .with_editor(vmf::Editor::new(VISGROUP_MODELS)),
+    ))
 }
 
 fn main() -> anyhow::Result<()> {
-    let schema: Vec<Block> = serde_json::from_str(&std::fs::read_to_string("jaybirthday.json")?)?;
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let merge = take_flag(&mut args, "--merge");
+    let mapping = mapping::TextureMap::load(
+        take_flag(&mut args, "--textures").as_deref(),
+        take_flag(&mut args, "--assets").as_deref(),
+    )?;
+    let dither = take_switch(&mut args, "--dither");
+    let optimize = take_switch(&mut args, "--optimize");
+    let climb_vines = take_switch(&mut args, "--climb-vines");
+    let hidden_texture = take_flag(&mut args, "--hidden").unwrap_or("NULL".to_string());
+    let target = match take_flag(&mut args, "--target").as_deref() {
+        None | Some("goldsrc") => Target::GoldSrc,
+        Some("source") => Target::Source,
+        Some(other) => anyhow::bail!("unknown target {}", other),
+    };
+    let seal_padding = take_flag(&mut args, "--seal-padding")
+        .map(|p| p.parse::<i64>())
+        .transpose()?
+        .unwrap_or(0);
+    let seal_mode = match take_flag(&mut args, "--seal").as_deref() {
+        None => None,
+        Some("hull") => Some(Seal::Hull),
+        Some("box") => Some(Seal::Box(seal_padding)),
+        Some(other) => anyhow::bail!("unknown seal {}", other),
+    };
+    let transform: transform::Transform = match take_flag(&mut args, "--transform") {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("bad transform {}: {}", path, e))?,
+        None => Default::default(),
+    };
+    let schema = load_blocks(&args)?;
     let mut voxels: HashMap<(usize, usize, usize), greedy::Voxel> = HashMap::new();
     let mut models: HashMap<(usize, usize, usize), greedy::Voxel> = HashMap::new();
     let mut torches: Vec<(usize, usize, usize)> = Vec::new();
+    let mut doors: BTreeMap<(usize, usize, usize), greedy::Voxel> = BTreeMap::new();
+    let mut ladders = BTreeMap::new();
+    let mut fluids: BTreeMap<(usize, usize, usize), (&str, u32)> = BTreeMap::new();
 
+    if schema.is_empty() {
+        anyhow::bail!("no blocks in selection");
+    }
     let min = (
         schema.iter().map(|b| b.pos.0).min().unwrap(),
         schema.iter().map(|b| b.pos.1).min().unwrap(),
@@ -484,21 +847,28 @@ fn main() -> anyhow::Result<()> {
         schema.iter().map(|b| b.pos.1).max().unwrap(),
         schema.iter().map(|b| b.pos.2).max().unwrap(),
     );
+    let size = (
+        (max.0 - min.0 + 1) as usize,
+        (max.1 - min.1 + 1) as usize,
+        (max.2 - min.2 + 1) as usize,
+    );
+    let transform = transform.with_size(size)?;
+    if target == Target::GoldSrc && !transform.fits(transform::GOLDSRC_LIMIT) {
+        println!(
+            "The map goes past ±{} units, GoldSrc won't load it. Try a smaller scale or \"center\": true",
+            transform::GOLDSRC_LIMIT
+        );
+    }
 
+    let mut signs = Vec::new();
     for block in schema {
-        if block.id.contains("door") {
-            continue;
-        }
         if block.id.contains("sign") {
+            // No text rendering in GoldSrc, but whoever's in Hammer can still read what it said
+            if let Some(text) = block.sign_text() {
+                signs.push((block.pos, text));
+            }
             continue;
         }
-        if block.id.contains("ladder") {
-            continue;
-        }
-        if block.id.contains("water") || block.id.contains("lava") {
-            continue;
-        }
-
         // Offset the negative so it starts at zero
         let pos = (
             (block.pos.0 - min.0) as usize,
@@ -516,10 +886,54 @@ fn main() -> anyhow::Result<()> {
         {
             continue;
         }
-        let old_pos = pos;
 
-        // Flip it on the Z axis because Hammer moment (janky hack to do it here)
-        let pos = (pos.0, pos.1, (max.2 - min.2) as usize - pos.2);
+        // Waterlogged blocks still get built, they just have water around them as well
+        let props = util::parse_properties(block.props.as_deref().unwrap_or(""));
+        // Underwater plants are nothing but the water around them for now
+        let underwater = [
+            "bubble_column",
+            "kelp",
+            "kelp_plant",
+            "seagrass",
+            "tall_seagrass",
+        ]
+        .contains(&block.id.as_str());
+        let fluid = match block.id.as_str() {
+            "water" => Some("water"),
+            "lava" => Some("lava"),
+            _ if underwater => Some("water"),
+            _ if props.get("waterlogged").is_some_and(|w| w == "true") => Some("water"),
+            _ => None,
+        };
+        if let Some(fluid) = fluid {
+            let level = match props.get("level") {
+                Some(level) if block.id == fluid => level.parse().unwrap_or(0),
+                _ => 0,
+            };
+            fluids.insert(pos, (fluid, level));
+        }
+        if underwater || block.id.contains("water") || block.id.contains("lava") {
+            continue;
+        }
+
+        if block.id == "ladder" || (climb_vines && shape::is_vine(&block.id)) {
+            ladders
+                .entry((block.id.clone(), block.props.clone().unwrap_or_default()))
+                .or_insert_with(HashMap::new)
+                .insert(
+                    pos,
+                    greedy::Voxel::new(block.id, block.props.unwrap_or("".to_string())),
+                );
+            continue;
+        }
+
+        if shape::is_door(&block.id) {
+            doors.insert(
+                pos,
+                greedy::Voxel::new(block.id, block.props.unwrap_or("".to_string())),
+            );
+            continue;
+        }
 
         if block.id == "campfire"
             || block.id == "grass"
@@ -557,192 +971,106 @@ fn main() -> anyhow::Result<()> {
             props = "".to_string();
         }
 
-        voxels.insert(pos, greedy::Voxel::new(block.id, props));
-    }
-
-    let spawn = (22., 34.3, 19.);
-    let camera = (spawn.0, spawn.1 + 5., spawn.2);
-    let light = (spawn.0, spawn.1 + 10., spawn.2);
-    let spawn = fix_coords((
-        spawn.0 * MC_TO_HAMMER,
-        spawn.1 * MC_TO_HAMMER,
-        spawn.2 * MC_TO_HAMMER,
-    ));
-    let camera = fix_coords((
-        camera.0 * MC_TO_HAMMER,
-        camera.1 * MC_TO_HAMMER,
-        camera.2 * MC_TO_HAMMER,
-    ));
-    let light = fix_coords((
-        light.0 * MC_TO_HAMMER,
-        light.1 * MC_TO_HAMMER,
-        light.2 * MC_TO_HAMMER,
-    ));
-
-    let mut id = 0;
-    let mut vmf = format!(
-        r#"
-versioninfo
-{{
-  "editorversion" "400"
-  "editorbuild" "2959"
-  "mapversion" "1"
-  "formatversion" "100"
-  "prefab" "0"
-}}
-viewsettings
-{{
-  "bSnapToGrid" "1"
-  "bShowGrid" "1"
-  "bShow3DGrid" "0"
-  "nGridSpacing" "2"
-}}
-cameras
-{{
-  "activecamera" "0"
-  camera
-  {{
-    "position" "[{} {} {}]"
-    "look" "[0 90 0]"
-  }}
-}}
-cordon
-{{
-  "mins" "(0 0 0)"
-  "maxs" "(0 0 0)"
-  "active" "0"
-}}
-"#,
-        camera.0, camera.1, camera.2
-    );
-
-    vmf += r#"
-visgroups
-{
-  visgroup
-  {
-    "name" "Generated World"
-    "visgroupid" "1"
-    "color" "255 255 255"
-    "visible" "1"
-  }
-
-  visgroup
-  {
-    "name" "Entities"
-    "visgroupid" "2"
-    "color" "255 255 255"
-    "visible" "1"
-  }
-
-  visgroup
-  {
-    "name" "Lights"
-    "visgroupid" "3"
-    "color" "255 255 255"
-    "visible" "1"
-  }
-
-  visgroup
-  {
-    "name" "Missing Textures"
-    "visgroupid" "4"
-    "color" "255 255 255"
-    "visible" "1"
-  }
-
-  visgroup
-  {
-    "name" "Models"
-    "visgroupid" "5"
-    "color" "255 255 255"
-    "visible" "1"
-  }
-}
-"#;
-
-    vmf = vmf
-        + &format!(
-            r#"
-entity
-{{
-  "id" "{}"
-  "classname" "info_player_start"
-  "origin" "{} {} {}"
-  "angles" "0 90 0"
-  editor
-  {{
-    "color" "0 255 0"
-    "visgroupid" "2"
-    "visgroupshown" "1"
-  }}
-}}
-"#,
-            id,
-            spawn.0 - (MC_TO_HAMMER / 2.),
-            spawn.1 - (MC_TO_HAMMER / 2.),
-            spawn.2 - (MC_TO_HAMMER / 2.)
+        // Shaped blocks can only merge along the axes they fill all the way across
+        let stretch = shape::parts(&block.id, &util::parse_properties(&props))
+            .map(|parts| shape::stretch_axes(&parts))
+            .unwrap_or([true; 3]);
+        // Brush entities have nothing to hide the insides of overlapping boxes
+        let overlap = is_world_brush(&block.id);
+        voxels.insert(
+            pos,
+            greedy::Voxel::new(block.id, props)
+                .with_stretch(stretch)
+                .with_overlap(overlap),
         );
-    id += 1;
-
-    vmf = vmf
-        + &format!(
-            r#"
-entity
-{{
-  "id" "{}"
-  "classname" "light_environment"
-  "origin" "{} {} {}"
-  "_light" "240 240 255 170"
-  "pitch" "-90"
-  editor
-  {{
-    "color" "220 30 220"
-    "visgroupid" "3"
-    "visgroupshown" "1"
-  }}
-}}
-"#,
-            id, light.0, light.1, light.2
-        );
-    id += 1;
-    vmf = vmf
-        + &format!(
-            r#"
-entity
-{{
-  "id" "{}"
-  "classname" "info_texlights"
-  "origin" "{} {} {}"
-  "glowstone" "171 131 83 1000"
-}}
-"#,
-            id, light.0, light.1, light.2
-        );
-    id += 1;
-
-    let mut world = format!(
-        r#"
-world
-{{
-  "id" "{}"
-  "mapversion" "1"
-  "classname" "worldspawn"
-  "_generator" "absolute gangstas hacker technology"
-  "defaultteam" "0"
-  "newunit" "0"
-  "gametitle" "0"
-  "startdark" "0"
-  "MaxRange" "8192"
-  "sounds" "1"
-  "skyname" "jaymc"
-"#,
-        id
+    }
+
+    // Picked back when the Z axis got flipped before anything else, hence counting from the end
+    let spawn = (22., 34.3, size.2 as f64 - 19.);
+    let camera = transform.point((spawn.0, spawn.1 + 5., spawn.2));
+    let light = transform.point((spawn.0, spawn.1 + 10., spawn.2));
+    let spawn = transform.point(spawn);
+
+    let mut vmf = vmf::Vmf {
+        version_info: Default::default(),
+        visgroups: vec![
+            vmf::VisGroup::new("Generated World", VISGROUP_WORLD),
+            vmf::VisGroup::new("Entities", VISGROUP_ENTITIES),
+            vmf::VisGroup::new("Lights", VISGROUP_LIGHTS),
+            vmf::VisGroup::new("Missing Textures", VISGROUP_MISSING),
+            vmf::VisGroup::new("Models", VISGROUP_MODELS),
+        ],
+        view_settings: Default::default(),
+        world: vmf::World {
+            properties: [
+                ("mapversion", "1"),
+                ("classname", "worldspawn"),
+                ("_generator", "absolute gangstas hacker technology"),
+                ("defaultteam", "0"),
+                ("newunit", "0"),
+                ("gametitle", "0"),
+                ("startdark", "0"),
+                ("MaxRange", "8192"),
+                ("sounds", "1"),
+                ("skyname", "jaymc"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            solids: Vec::new(),
+            extra: Vec::new(),
+        },
+        entities: Vec::new(),
+        cameras: vmf::Cameras {
+            active: 0,
+            cameras: vec![vmf::Camera {
+                position: camera,
+                look: (0., 90., 0.),
+            }],
+        },
+        cordon: Default::default(),
+        extra: Vec::new(),
+    };
+
+    vmf.entities.push(
+        vmf::Entity::new("info_player_start")
+            .with_origin((
+                spawn.0 - (transform.scale / 2.),
+                spawn.1 - (transform.scale / 2.),
+                spawn.2 - (transform.scale / 2.),
+            ))
+            .with("angles", "0 90 0")
+            .with_editor(vmf::Editor::new(VISGROUP_ENTITIES)),
+    );
+    vmf.entities.push(
+        vmf::Entity::new("light_environment")
+            .with_origin(light)
+            .with("_light", "240 240 255 170")
+            .with("pitch", "-90")
+            .with_editor(vmf::Editor::new(VISGROUP_LIGHTS).with_color((220, 30, 220))),
     );
-    id += 1;
+    vmf.entities.push(
+        vmf::Entity::new("info_texlights")
+            .with_origin(light)
+            .with("glowstone", "171 131 83 1000"),
+    );
+    // info_null gets thrown away by the compilers, so these only ever show up in Hammer
+    for (pos, text) in signs {
+        let mut editor = vmf::Editor::new(VISGROUP_ENTITIES);
+        editor.extra.push(("comments".to_string(), text));
+        vmf.entities.push(
+            vmf::Entity::new("info_null")
+                .with_origin(transform.point((
+                    (pos.0 - min.0) as f64 + 0.5,
+                    (pos.1 - min.1) as f64 + 0.5,
+                    (pos.2 - min.2) as f64 + 0.5,
+                )))
+                .with_editor(editor),
+        );
+    }
 
     // Merge brushes together - a wall of the same block should be one continuous brush
-    let boxes = greedy::best_greedy(&voxels);
+    let boxes = greedy::best_greedy(&voxels, optimize);
     println!("{} boxes", boxes.len());
 
     let mut fills: Vec<String> = Vec::new();
@@ -761,105 +1089,432 @@ world
         .collect::<Vec<_>>();
     let mut missing_textures = Vec::new();
 
-    let mut queued_entities = Vec::new();
+    // Faces buried against opaque blocks get the hidden texture, so the compilers skip them
+    let mut opaque_blocks = HashMap::new();
+    let opaque = voxels
+        .iter()
+        .filter(|(_, voxel)| {
+            *opaque_blocks
+                .entry((voxel.id.clone(), voxel.properties.clone()))
+                .or_insert_with(|| is_opaque(voxel, &mapping))
+        })
+        .map(|(pos, _)| *pos)
+        .collect::<HashSet<_>>();
 
     for r#box in boxes {
-        if r#box.id.contains("glass") && !r#box.id.contains("tinted") {
-            let mut entity = format!(
-                r#"
-    entity
-    {{
-        "id" "{}"
-        "classname" "func_breakable"
-        "rendermode" "2"
-        "renderamt" "255"
-        "health" "25"
-        "spawnflags" "256"
-        "zhlt_embedlightmap" "1"
-"#,
-                id
+        let brushes = build_brushes(
+            &r#box,
+            &mapping,
+            &textures,
+            &mut missing_textures,
+            &opaque,
+            &hidden_texture,
+            target,
+            &transform,
+        );
+        if shape::is_pane(&r#box.id) {
+            // Thin enough that nobody's breaking them, they just need to render masked
+            let mut entity = vmf::Entity::new("func_wall")
+                .with("rendermode", "4")
+                .with("renderamt", "255")
+                .with("zhlt_lightflags", "2")
+                .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            entity.solids.extend(brushes);
+            vmf.entities.push(entity);
+        } else if r#box.id.contains("glass") && !r#box.id.contains("tinted") {
+            let mut entity = vmf::Entity::new("func_breakable")
+                .with("rendermode", "2")
+                .with("renderamt", "255")
+                .with("health", "25")
+                .with("spawnflags", "256")
+                .with("zhlt_embedlightmap", "1")
+                .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            entity.solids.extend(brushes);
+            vmf.entities.push(entity);
+        } else if !is_world_brush(&r#box.id) {
+            let mut entity = vmf::Entity::new("func_illusionary")
+                .with("rendermode", "4")
+                .with("renderamt", "255")
+                .with("zhlt_lightflags", "2")
+                .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            entity.solids.extend(brushes);
+            vmf.entities.push(entity);
+        } else {
+            vmf.world.solids.extend(brushes);
+        }
+    }
+
+    for (pos, voxel) in &doors {
+        let props = util::parse_properties(&voxel.properties);
+        let is_half = |pos: (usize, usize, usize), half: &str| {
+            doors.get(&pos).is_some_and(|other| {
+                other.id == voxel.id
+                    && util::parse_properties(&other.properties).get("half") == Some(&half.into())
+            })
+        };
+        // Both halves of a door go in one entity, built from the bottom
+        let mut halves = vec![(*pos, voxel)];
+        match props.get("half").map(|s| s.as_str()) {
+            Some("upper") if is_half((pos.0, pos.1.wrapping_sub(1), pos.2), "lower") => continue,
+            Some("lower") if is_half((pos.0, pos.1 + 1, pos.2), "upper") => {
+                halves.push((
+                    (pos.0, pos.1 + 1, pos.2),
+                    &doors[&(pos.0, pos.1 + 1, pos.2)],
+                ));
+            }
+            _ => {}
+        }
+        let Some((hinge, axis)) = shape::hinge(&voxel.id, &props) else {
+            continue;
+        };
+
+        // Nothing's hidden on something that moves
+        let mut solids = halves
+            .iter()
+            .flat_map(|(pos, half)| {
+                let r#box = greedy::Box {
+                    min: *pos,
+                    max: *pos,
+                    id: half.id.clone(),
+                    properties: half.properties.clone(),
+                };
+                build_brushes(
+                    &r#box,
+                    &mapping,
+                    &textures,
+                    &mut missing_textures,
+                    &HashSet::new(),
+                    &hidden_texture,
+                    target,
+                    &transform,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // The origin brush's center is what the door turns around
+        let hinge = (
+            pos.0 as f64 + hinge[0],
+            pos.1 as f64 + hinge[1] * halves.len() as f64,
+            pos.2 as f64 + hinge[2],
+        );
+        let nudge = 1. / 16.;
+        solids.push(tool_brush(
+            (hinge.0 - nudge, hinge.1 - nudge, hinge.2 - nudge),
+            (hinge.0 + nudge, hinge.1 + nudge, hinge.2 + nudge),
+            "ORIGIN",
+            target,
+            &transform,
+        ));
+
+        let mut spawnflags = hinge_flags(axis, &transform);
+        if props.get("open").is_some_and(|o| o == "true") {
+            spawnflags |= 1;
+        }
+
+        let mut entity = vmf::Entity::new("func_door_rotating")
+            .with("distance", "90")
+            .with("speed", "200")
+            .with("wait", "-1")
+            .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+        if voxel.id.starts_with("iron_") {
+            // Named doors don't open when touched, and without "Use Only" the player can't use
+            // them either, so only something triggering them can
+            entity.set(
+                "targetname",
+                format!("{}_{}_{}_{}", voxel.id, pos.0, pos.1, pos.2),
             );
-            id += 1;
-
-            entity += &build_brush(&mut id, &r#box, &voxels, &textures, &mut missing_textures);
-            entity += "\n    }\n";
-            queued_entities.push(entity);
-        } else if r#box.id.contains("leaves") {
-            let mut entity = format!(
-                r#"
-    entity
-    {{
-        "id" "{}"
-        "classname" "func_illusionary"
-        "rendermode" "4"
-        "renderamt" "255"
-        "zhlt_lightflags" "2"
-"#,
-                id
+        } else {
+            spawnflags |= 256;
+        }
+        entity.set("spawnflags", spawnflags);
+        entity.solids = solids;
+        vmf.entities.push(entity);
+    }
+
+    // Each body of water or lava is one func_water. Anything with more of the same on top of it
+    // fills its block, the rest sits as high as its level says
+    for fluid in ["water", "lava"] {
+        let mut voxels = HashMap::new();
+        for (pos, (kind, level)) in &fluids {
+            if *kind != fluid {
+                continue;
+            }
+            let covered = fluids
+                .get(&(pos.0, pos.1 + 1, pos.2))
+                .is_some_and(|(above, _)| *above == fluid);
+            let props = format!("level={}", if covered { 8 } else { *level });
+            let stretch = shape::parts(fluid, &util::parse_properties(&props))
+                .map(|parts| shape::stretch_axes(&parts))
+                .unwrap_or([true; 3]);
+            voxels.insert(
+                *pos,
+                greedy::Voxel::new(fluid.to_string(), props).with_stretch(stretch),
             );
-            id += 1;
+        }
 
-            entity += &build_brush(&mut id, &r#box, &voxels, &textures, &mut missing_textures);
-            entity += "\n    }\n";
-            queued_entities.push(entity);
-        } else {
-            world =
-                world + &build_brush(&mut id, &r#box, &voxels, &textures, &mut missing_textures);
+        let mut positions = voxels.keys().copied().collect::<Vec<_>>();
+        positions.sort();
+        for region in greedy::flood(&positions) {
+            // Only the same level merges, the mesher only checks ids
+            let mut levels = BTreeMap::new();
+            for pos in region {
+                let voxel = &voxels[&pos];
+                levels
+                    .entry(voxel.properties.clone())
+                    .or_insert_with(HashMap::new)
+                    .insert(pos, voxel.clone());
+            }
+            let boxes = levels
+                .values()
+                .flat_map(|level| {
+                    let directions = [
+                        greedy::GreedyDirection::X,
+                        greedy::GreedyDirection::Z,
+                        greedy::GreedyDirection::Y,
+                    ];
+                    greedy::greedy(level, &directions)
+                })
+                .collect::<Vec<_>>();
+
+            let mut entity = vmf::Entity::new("func_water")
+                .with("skin", if fluid == "lava" { "-5" } else { "-3" })
+                .with("WaveHeight", "0")
+                .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            if fluid == "water" {
+                entity.set("rendermode", "2");
+                entity.set("renderamt", "160");
+            }
+            for r#box in &boxes {
+                entity.solids.extend(build_brushes(
+                    r#box,
+                    &mapping,
+                    &textures,
+                    &mut missing_textures,
+                    &opaque,
+                    &hidden_texture,
+                    target,
+                    &transform,
+                ));
+            }
+            vmf.entities.push(entity);
+
+            if fluid == "lava" {
+                // Burns every half a second, about as fast as the real thing
+                let mut hurt = vmf::Entity::new("trigger_hurt")
+                    .with("dmg", "20")
+                    .with("damagetype", "8")
+                    .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+                for r#box in &boxes {
+                    hurt.solids.push(tool_brush(
+                        (r#box.min.0 as f64, r#box.min.1 as f64, r#box.min.2 as f64),
+                        (
+                            (r#box.max.0 + 1) as f64,
+                            (r#box.max.1 + 1) as f64,
+                            (r#box.max.2 + 1) as f64,
+                        ),
+                        "AAATRIGGER",
+                        target,
+                        &transform,
+                    ));
+                }
+                vmf.entities.push(hurt);
+            }
         }
     }
 
-    world += "\n}\n";
+    // Ladders only ever go straight up, so they're merged in columns
+    for ((id, props), voxels) in &ladders {
+        let props = util::parse_properties(props);
+        let volume = shape::climb_volume(id, &props).unwrap_or_default();
+        for r#box in greedy::greedy(voxels, &[greedy::GreedyDirection::Y]) {
+            let mut entity = vmf::Entity::new("func_illusionary")
+                .with("rendermode", "4")
+                .with("renderamt", "255")
+                .with("zhlt_lightflags", "2")
+                .with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            entity.solids = build_brushes(
+                &r#box,
+                &mapping,
+                &textures,
+                &mut missing_textures,
+                &opaque,
+                &hidden_texture,
+                target,
+                &transform,
+            );
+            vmf.entities.push(entity);
+
+            let mut ladder =
+                vmf::Entity::new("func_ladder").with_editor(vmf::Editor::new(VISGROUP_WORLD));
+            for (from, to) in &volume {
+                ladder.solids.push(tool_brush(
+                    (
+                        r#box.min.0 as f64 + from[0],
+                        r#box.min.1 as f64 + from[1],
+                        r#box.min.2 as f64 + from[2],
+                    ),
+                    (
+                        r#box.max.0 as f64 + to[0],
+                        r#box.max.1 as f64 + to[1],
+                        r#box.max.2 as f64 + to[2],
+                    ),
+                    "AAATRIGGER",
+                    target,
+                    &transform,
+                ));
+            }
+            vmf.entities.push(ladder);
+        }
+    }
 
     for torch in torches {
-        let pos = (
-            (torch.0 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-            (torch.1 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-            (torch.2 as f64 * MC_TO_HAMMER) + (MC_TO_HAMMER / 2.),
-        );
-        let pos = fix_coords(pos);
-        let torch = format!(
-            r#"
-    entity
-    {{
-      "id" "{}"
-      "classname" "light"
-      "origin" "{} {} {}"
-      "angles" "0 0 0"
-      "_falloff" "0"
-      "_fade" "1.0"
-      "style" "0"
-      "_light" "255 255 255 100"
-      "light" "255 255 255 100"
-
-      editor
-      {{
-        "color" "0 255 0"
-        "visgroupid" "3"
-        "visgroupshown" "1"
-      }}
-    }}
-"#,
-            id, pos.0, pos.1, pos.2
+        let pos = transform.point((
+            torch.0 as f64 + 0.5,
+            torch.1 as f64 + 0.5,
+            torch.2 as f64 + 0.5,
+        ));
+        vmf.entities.push(
+            vmf::Entity::new("light")
+                .with_origin(pos)
+                .with("angles", "0 0 0")
+                .with("_falloff", "0")
+                .with("_fade", "1.0")
+                .with("style", "0")
+                .with("_light", "255 255 255 100")
+                .with("light", "255 255 255 100")
+                .with_editor(vmf::Editor::new(VISGROUP_LIGHTS)),
         );
-        id += 1;
-        queued_entities.push(torch);
     }
 
     let mut exported_models = Vec::new();
-    for (pos, voxel) in models {
-        if let Some(model) = build_model(&mut id, pos, &voxel, &mut exported_models) {
-            queued_entities.push(model);
+    // Source can't load the GoldSrc .mdls, so there's no point building them
+    if target == Target::GoldSrc {
+        for (pos, voxel) in models {
+            if let Some(model) = build_model(pos, &voxel, &mut exported_models, dither, &transform)?
+            {
+                vmf.entities.push(model);
+            }
+        }
+    }
+
+    // Only full, solid blocks are sure to seal, slabs and fences leave gaps hlbsp gets through
+    let to_cell = |p: &(usize, usize, usize)| (p.0 as i64, p.1 as i64, p.2 as i64);
+    let mut seals = opaque.iter().map(to_cell).collect::<HashSet<_>>();
+    if let Some(seal_mode) = seal_mode {
+        let mut occupied = voxels.keys().map(to_cell).collect::<HashSet<_>>();
+        occupied.extend(
+            leak_targets(&vmf, &transform)
+                .iter()
+                .map(|(_, _, cell)| *cell),
+        );
+        let cells = match seal_mode {
+            Seal::Hull => seal::hull(&occupied),
+            Seal::Box(padding) => seal::shell(&occupied, padding),
+        };
+        let boxes = seal::boxes(&cells);
+        println!("Sealing the map with {} sky brushes", boxes.len());
+        for (min, max) in boxes {
+            vmf.world.solids.push(tool_brush(
+                (min.0 as f64, min.1 as f64, min.2 as f64),
+                ((max.0 + 1) as f64, (max.1 + 1) as f64, (max.2 + 1) as f64),
+                "SKY",
+                target,
+                &transform,
+            ));
         }
+        seals.extend(cells);
     }
 
-    for entity in queued_entities {
-        vmf += &entity;
-        vmf += "\n";
+    // Keep the hand-edited parts of a previous conversion and only swap out what we generate
+    if let Some(previous) = merge {
+        let mut previous: vmf::Vmf = std::fs::read_to_string(&previous)?.parse()?;
+        previous.replace_visgroups(vmf, GENERATED_VISGROUPS);
+        vmf = previous;
+        // Whatever got added by hand to seal it counts too
+        for solid in &vmf.world.solids {
+            seals.extend(solid_cells(solid, &transform));
+        }
     }
 
-    vmf = vmf + "\n" + &world;
-    std::fs::write("jaybirthday.vmf", vmf)?;
+    // hlbsp leaks if any entity can see the void, so check that before it gets the chance
+    let targets = leak_targets(&vmf, &transform);
+    let cells = targets.iter().map(|(_, _, cell)| *cell).collect::<Vec<_>>();
+    match seal::find_leak(&seals, &cells) {
+        Some((i, path)) => {
+            let (classname, origin, _) = &targets[i];
+            println!(
+                "LEAK: {} at {} {} {} can be reached from outside the map, along:",
+                classname, origin.0, origin.1, origin.2
+            );
+            // Same format as hlbsp's pointfiles, so Hammer can draw it
+            let mut points = Vec::new();
+            for cell in seal::corners(&path) {
+                let center = transform.point((
+                    cell.0 as f64 + 0.5,
+                    cell.1 as f64 + 0.5,
+                    cell.2 as f64 + 0.5,
+                ));
+                println!("  {} {} {}", center.0, center.1, center.2);
+                points.push(format!("{} {} {}", center.0, center.1, center.2));
+            }
+            std::fs::write("jaybirthday.pts", points.join("\n"))?;
+        }
+        None => {
+            println!("No leaks");
+            // Don't leave an old leak's pointfile around to confuse anyone
+            let _ = std::fs::remove_file("jaybirthday.pts");
+        }
+    }
+
+    match target {
+        Target::GoldSrc => {
+            vmf.world.set("wad", "jaybirthday.wad");
+            let names = wad::allocate_names(&mut vmf);
+            std::fs::write("texture_names.txt", wad::manifest(&names, "../textures"))?;
+            let skipped = wad::write("jaybirthday.wad", &names, "../textures")?;
+            for texture in skipped {
+                println!("Couldn't add {} to the WAD", texture);
+            }
+            std::fs::write("jaybirthday.map", map::to_valve220(&vmf))?;
+        }
+        Target::Source => {
+            vmf.entities = vmf
+                .entities
+                .into_iter()
+                .filter_map(source::convert_entity)
+                .collect();
+            let missing = source::write_materials(&vmf, "../textures", "materials")?;
+            for material in missing {
+                println!("No texture for material {}", material);
+            }
+        }
+    }
+    std::fs::write("jaybirthday.vmf", vmf.to_string())?;
     std::fs::write("missing.txt", missing_textures.join("\n"))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirroring_reverses_doors() {
+        let mut mirrored = transform::Transform::default();
+        mirrored.axes[0] = transform::Direction::West;
+        assert!(mirrored.mirrored());
+
+        let plain = transform::Transform::default();
+        // A door turning around the vertical and a trapdoor turning around z, both going the
+        // other way in a mirror
+        for axis in [[0., 1., 0.], [0., -1., 0.], [0., 0., 1.], [0., 0., -1.]] {
+            let before = hinge_flags(axis, &plain);
+            let after = hinge_flags(axis, &mirrored);
+            assert_eq!(before & !2, after & !2, "{axis:?}");
+            assert_ne!(before & 2, after & 2, "{axis:?}");
+        }
+        assert_eq!(hinge_flags([0., 1., 0.], &plain), 0);
+        assert_eq!(hinge_flags([0., -1., 0.], &plain), 2);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::vmf;
+use std::fmt::Write;
+
+fn point(p: vmf::Vec3) -> String {
+    format!("( {} {} {} )", p.0, p.1, p.2)
+}
+
+fn axis(a: &vmf::TextureAxis) -> String {
+    format!("[ {} {} {} {} ]", a.axis.0, a.axis.1, a.axis.2, a.offset)
+}
+
+pub fn sub(a: vmf::Vec3, b: vmf::Vec3) -> vmf::Vec3 {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+pub fn cross(a: vmf::Vec3, b: vmf::Vec3) -> vmf::Vec3 {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+pub fn dot(a: vmf::Vec3, b: vmf::Vec3) -> f64 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn normalize(a: vmf::Vec3) -> vmf::Vec3 {
+    let len = dot(a, a).sqrt();
+    if len == 0. {
+        return a;
+    }
+    (a.0 / len, a.1 / len, a.2 / len)
+}
+
+/// Hammer quietly fixes texture axes that lie along the face normal when it loads a VMF, hlcsg
+/// just errors out. Swap those for the face-aligned axes hlcsg would pick itself.
+fn fix_axes(side: &vmf::Side) -> (vmf::TextureAxis, vmf::TextureAxis) {
+    let [a, b, c] = side.plane;
+    let normal = normalize(cross(sub(a, b), sub(c, b)));
+    let (u, v) = (normalize(side.uaxis.axis), normalize(side.vaxis.axis));
+
+    let degenerate =
+        dot(u, normal).abs() > 0.999 || dot(v, normal).abs() > 0.999 || dot(u, v).abs() > 0.999;
+    if !degenerate {
+        return (side.uaxis, side.vaxis);
+    }
+
+    // Quake's baseaxis table: floors and ceilings, then X walls, then Y walls
+    let (u, v) = if normal.2.abs() >= normal.0.abs() && normal.2.abs() >= normal.1.abs() {
+        ((1., 0., 0.), (0., -1., 0.))
+    } else if normal.0.abs() >= normal.1.abs() {
+        ((0., 1., 0.), (0., 0., -1.))
+    } else {
+        ((1., 0., 0.), (0., 0., -1.))
+    };
+
+    (
+        vmf::TextureAxis {
+            axis: u,
+            ..side.uaxis
+        },
+        vmf::TextureAxis {
+            axis: v,
+            ..side.vaxis
+        },
+    )
+}
+
+fn write_solid(map: &mut String, solid: &vmf::Solid) {
+    map.push_str("{\n");
+    for side in &solid.sides {
+        let (uaxis, vaxis) = fix_axes(side);
+        // Same point order as the VMF, Hammer and hlcsg agree on winding
+        let _ = writeln!(
+            map,
+            "{} {} {} {} {} {} {} {} {}",
+            point(side.plane[0]),
+            point(side.plane[1]),
+            point(side.plane[2]),
+            side.material,
+            axis(&uaxis),
+            axis(&vaxis),
+            side.rotation,
+            uaxis.scale,
+            vaxis.scale
+        );
+    }
+    map.push_str("}\n");
+}
+
+fn write_kv(map: &mut String, key: &str, value: &str) {
+    let _ = writeln!(map, "\"{}\" \"{}\"", vmf::escape(key), vmf::escape(value));
+}
+
+// Solids the mapper hid in the editor still belong in the compile
+fn hidden_solids(extra: &[vmf::Node]) -> impl Iterator<Item = vmf::Solid> + '_ {
+    extra
+        .iter()
+        .filter(|n| n.name == "hidden")
+        .flat_map(|n| n.children.iter().filter(|c| c.name == "solid"))
+        .filter_map(|n| vmf::parse_solid(n).ok())
+}
+
+/// Writes the map as a Valve220 .map that hlcsg can compile directly.
+pub fn to_valve220(vmf: &vmf::Vmf) -> String {
+    let mut map = "// Game: Half-Life\n// Format: Valve\n".to_string();
+
+    map.push_str("{\n");
+    for (key, value) in &vmf.world.properties {
+        if key != "mapversion" {
+            write_kv(&mut map, key, value);
+        }
+    }
+    write_kv(&mut map, "mapversion", "220");
+    for solid in &vmf.world.solids {
+        write_solid(&mut map, solid);
+    }
+    for solid in hidden_solids(&vmf.world.extra) {
+        write_solid(&mut map, &solid);
+    }
+    map.push_str("}\n");
+
+    for entity in &vmf.entities {
+        map.push_str("{\n");
+        write_kv(&mut map, "classname", &entity.classname);
+        for (key, value) in &entity.properties {
+            write_kv(&mut map, key, value);
+        }
+        for solid in &entity.solids {
+            write_solid(&mut map, solid);
+        }
+        for solid in hidden_solids(&entity.extra) {
+            write_solid(&mut map, &solid);
+        }
+        map.push_str("}\n");
+    }
+
+    map
+}
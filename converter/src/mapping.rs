@@ -0,0 +1,154 @@
+use crate::assets;
+use crate::util::{Face, Rotation};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Which texture goes on which face of a block. The compiled-in defaults live in mapping.json,
+/// and a file passed with `--textures` is layered on top, block by block. Blocks neither of them
+/// mention are looked up in the `--assets` blockstates and models, if there are any.
+pub struct TextureMap {
+    blocks: HashMap<String, Entry>,
+    assets: Option<assets::Assets>,
+}
+
+/// Textures for each face of the block as if it faced north with its axis up, and how to turn
+/// it to match its properties. Faces with no texture fall back to the block's own name.
+#[derive(Debug, Clone, Default)]
+pub struct BlockTextures {
+    pub faces: [Option<String>; 6],
+    pub rotation: Rotation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    /// One texture on every face.
+    All(String),
+    Faces(Box<Faces>),
+}
+
+/// Faces are resolved from most to least specific: the direction itself, top/bottom, end (top and
+/// bottom), front, side (the four horizontal faces), and finally all. Directions are for the
+/// block before it's rotated, and the front is north. Blocks with a front get turned to face
+/// wherever `front_property` points.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Faces {
+    north: Option<String>,
+    south: Option<String>,
+    east: Option<String>,
+    west: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    end: Option<String>,
+    front: Option<String>,
+    side: Option<String>,
+    all: Option<String>,
+    #[serde(default = "default_front_property")]
+    front_property: String,
+}
+
+fn default_front_property() -> String {
+    "facing".to_string()
+}
+
+/// Rotation for blocks whose front (north when unrotated) points at `facing`.
+fn facing_rotation(facing: &str) -> Option<Rotation> {
+    let (x, y) = match facing {
+        "north" => (0, 0),
+        "east" => (0, 90),
+        "south" => (0, 180),
+        "west" => (0, 270),
+        "up" => (270, 0),
+        "down" => (90, 0),
+        _ => return None,
+    };
+    Some(Rotation { x, y })
+}
+
+/// Pillars (logs, basalt, ...) lie down along their `axis`.
+fn axis_rotation(props: &HashMap<String, String>) -> Rotation {
+    match props.get("axis").map(|s| s.as_str()) {
+        Some("x") => Rotation { x: 90, y: 90 },
+        Some("z") => Rotation { x: 90, y: 0 },
+        _ => Rotation::default(),
+    }
+}
+
+impl Faces {
+    fn get(&self, face: Face) -> Option<&String> {
+        let direction = match face {
+            Face::North => &self.north,
+            Face::South => &self.south,
+            Face::East => &self.east,
+            Face::West => &self.west,
+            Face::Top => &self.top,
+            Face::Bottom => &self.bottom,
+        };
+        let is_end = matches!(face, Face::Top | Face::Bottom);
+
+        direction
+            .as_ref()
+            .or(self.end.as_ref().filter(|_| is_end))
+            .or(self.front.as_ref().filter(|_| face == Face::North))
+            .or(self.side.as_ref().filter(|_| !is_end))
+            .or(self.all.as_ref())
+    }
+
+    fn rotation(&self, props: &HashMap<String, String>) -> Rotation {
+        if self.front.is_none() {
+            return axis_rotation(props);
+        }
+
+        if let Some(facing) = props.get(&self.front_property) {
+            return facing_rotation(facing).unwrap_or_default();
+        }
+        // Signs, banners and heads use 16 steps, starting from south
+        match props.get("rotation").and_then(|r| r.parse::<i32>().ok()) {
+            Some(steps) => Rotation {
+                x: 0,
+                y: ((steps + 2) / 4 * 90 + 180) % 360,
+            },
+            // Blocks without the property face north, like crafting tables
+            None => Rotation::default(),
+        }
+    }
+}
+
+impl TextureMap {
+    pub fn load(overrides: Option<&str>, assets: Option<&str>) -> anyhow::Result<Self> {
+        let mut blocks: HashMap<String, Entry> =
+            serde_json::from_str(include_str!("mapping.json"))?;
+        if let Some(path) = overrides {
+            let extra: HashMap<String, Entry> =
+                serde_json::from_str(&std::fs::read_to_string(path)?)
+                    .map_err(|e| anyhow::anyhow!("bad texture mapping {}: {}", path, e))?;
+            blocks.extend(extra);
+        }
+        Ok(Self {
+            blocks,
+            assets: assets.map(assets::Assets::new).transpose()?,
+        })
+    }
+
+    pub fn textures(&self, id: &str, props: &HashMap<String, String>) -> BlockTextures {
+        match self.blocks.get(id) {
+            Some(Entry::All(texture)) => BlockTextures {
+                faces: Face::ALL.map(|_| Some(texture.clone())),
+                rotation: axis_rotation(props),
+            },
+            Some(Entry::Faces(faces)) => BlockTextures {
+                faces: Face::ALL.map(|face| faces.get(face).cloned()),
+                rotation: faces.rotation(props),
+            },
+            None => self
+                .assets
+                .as_ref()
+                .and_then(|assets| assets.textures(id, props))
+                .unwrap_or_else(|| BlockTextures {
+                    faces: Default::default(),
+                    rotation: axis_rotation(props),
+                }),
+        }
+    }
+}
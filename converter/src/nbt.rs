@@ -0,0 +1,284 @@
+use anyhow::{bail, Context};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::{collections::BTreeMap, io::Read};
+
+// Not every payload is read by the loaders, but they still have to be parsed past
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum Tag {
+    End,
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(BTreeMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(v) => Some(*v as i64),
+            Tag::Short(v) => Some(*v as i64),
+            Tag::Int(v) => Some(*v as i64),
+            Tag::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        self.as_i64().map(|v| v as i32)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(list) => Some(list.as_slice()),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&BTreeMap<String, Tag>> {
+        match self {
+            Tag::Compound(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Tag::LongArray(arr) => Some(arr.as_slice()),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Tag::ByteArray(arr) => Some(arr.as_slice()),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Tag::IntArray(arr) => Some(arr.as_slice()),
+            _ => None,
+        }
+    }
+
+    // Shorthands for the lookups the loaders do constantly
+    pub fn int(&self, key: &str) -> anyhow::Result<i32> {
+        self.get(key)
+            .and_then(|t| t.as_i32())
+            .with_context(|| format!("missing int tag {}", key))
+    }
+
+    pub fn string(&self, key: &str) -> anyhow::Result<&str> {
+        self.get(key)
+            .and_then(|t| t.as_str())
+            .with_context(|| format!("missing string tag {}", key))
+    }
+
+    pub fn list(&self, key: &str) -> anyhow::Result<&[Tag]> {
+        self.get(key)
+            .and_then(|t| t.as_list())
+            .with_context(|| format!("missing list tag {}", key))
+    }
+
+    pub fn compound(&self, key: &str) -> anyhow::Result<&BTreeMap<String, Tag>> {
+        self.get(key)
+            .and_then(|t| t.as_compound())
+            .with_context(|| format!("missing compound tag {}", key))
+    }
+}
+
+/// Decompresses (gzip, zlib or nothing) and parses a named root compound.
+pub fn read(bytes: &[u8]) -> anyhow::Result<Tag> {
+    let data = decompress(bytes)?;
+    let mut reader = Reader {
+        data: &data,
+        pos: 0,
+    };
+
+    let ty = reader.u8()?;
+    if ty != 10 {
+        bail!("root tag is not a compound (type {})", ty);
+    }
+    // Root name is always empty in practice
+    reader.string()?;
+    reader.payload(ty)
+}
+
+pub fn read_file(path: &str) -> anyhow::Result<Tag> {
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path))?;
+    read(&bytes).with_context(|| format!("parsing {}", path))
+}
+
+pub fn decompress(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match bytes {
+        [0x1f, 0x8b, ..] => {
+            GzDecoder::new(bytes).read_to_end(&mut data)?;
+        }
+        [0x78, ..] => {
+            ZlibDecoder::new(bytes).read_to_end(&mut data)?;
+        }
+        _ => data.extend_from_slice(bytes),
+    }
+    Ok(data)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            bail!("unexpected end of NBT data at {}", self.pos);
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> anyhow::Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> anyhow::Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn len(&mut self) -> anyhow::Result<usize> {
+        // Negative lengths show up in empty lists written by some tools
+        Ok(self.i32()?.max(0) as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.i16()? as u16 as usize;
+        // Java's modified UTF-8 only differs for NUL and astral chars, close enough
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn payload(&mut self, ty: u8) -> anyhow::Result<Tag> {
+        Ok(match ty {
+            0 => Tag::End,
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(self.i16()?),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(self.i64()?),
+            5 => Tag::Float(f32::from_bits(self.i32()? as u32)),
+            6 => Tag::Double(f64::from_bits(self.i64()? as u64)),
+            7 => {
+                let len = self.len()?;
+                Tag::ByteArray(self.take(len)?.iter().map(|b| *b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let item_ty = self.u8()?;
+                let len = self.len()?;
+                // End items take no bytes, so a long list of them is only good for eating memory
+                if item_ty == 0 && len > 0 {
+                    bail!("list of {} End tags at {}", len, self.pos);
+                }
+                // Lengths come from the file, so don't trust them further than the bytes left
+                let mut list = Vec::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    list.push(self.payload(item_ty)?);
+                }
+                Tag::List(list)
+            }
+            10 => {
+                let mut map = BTreeMap::new();
+                loop {
+                    let ty = self.u8()?;
+                    if ty == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    map.insert(name, self.payload(ty)?);
+                }
+                Tag::Compound(map)
+            }
+            11 => {
+                let len = self.len()?;
+                let mut arr = Vec::with_capacity(len.min(self.remaining() / 4));
+                for _ in 0..len {
+                    arr.push(self.i32()?);
+                }
+                Tag::IntArray(arr)
+            }
+            12 => {
+                let len = self.len()?;
+                let mut arr = Vec::with_capacity(len.min(self.remaining() / 8));
+                for _ in 0..len {
+                    arr.push(self.i64()?);
+                }
+                Tag::LongArray(arr)
+            }
+            _ => bail!("unknown NBT tag type {} at {}", ty, self.pos),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Root compound named "" holding one list called "l"
+    fn list_file(item_ty: u8, len: i32) -> Vec<u8> {
+        let mut bytes = vec![10, 0, 0, 9, 0, 1, b'l', item_ty];
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn reads_an_empty_end_list() {
+        let tag = read(&list_file(0, 0)).unwrap();
+        assert_eq!(tag.list("l").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn rejects_a_long_end_list() {
+        assert!(read(&list_file(0, i32::MAX)).is_err());
+    }
+
+    #[test]
+    fn huge_lengths_run_out_of_bytes_instead_of_memory() {
+        assert!(read(&list_file(3, i32::MAX)).is_err());
+    }
+}
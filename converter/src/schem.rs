@@ -0,0 +1,114 @@
+use crate::{nbt, Block};
+use std::collections::HashMap;
+
+// Sponge packs palette indices as unsigned LEB128 varints
+fn read_varints(data: &[i8]) -> anyhow::Result<Vec<usize>> {
+    let mut values = Vec::new();
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    for byte in data.iter().map(|b| *b as u8) {
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+            if shift > 28 {
+                anyhow::bail!("varint in BlockData is too long");
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+fn vec3(tag: Option<&nbt::Tag>) -> (i32, i32, i32) {
+    match tag.and_then(|t| t.as_int_array()) {
+        Some([x, y, z, ..]) => (*x, *y, *z),
+        _ => (0, 0, 0),
+    }
+}
+
+/// Loads a Sponge schematic (WorldEdit's .schem), versions 1 through 3.
+pub fn load(path: &str) -> anyhow::Result<Vec<Block>> {
+    let root = nbt::read_file(path)?;
+    // v3 nests everything in a Schematic compound, v1/v2 name the root that instead
+    let schem = root.get("Schematic").unwrap_or(&root);
+    let version = schem.int("Version")?;
+
+    // Dimensions are unsigned shorts
+    let width = schem.int("Width")? as u16 as usize;
+    let height = schem.int("Height")? as u16 as usize;
+    let length = schem.int("Length")? as u16 as usize;
+    let offset = vec3(schem.get("Offset"));
+
+    let (container, data_key, entities_key) = match version {
+        1 => (schem, "BlockData", "TileEntities"),
+        2 => (schem, "BlockData", "BlockEntities"),
+        3 => (
+            schem
+                .get("Blocks")
+                .ok_or_else(|| anyhow::anyhow!("schematic has no Blocks"))?,
+            "Data",
+            "BlockEntities",
+        ),
+        v => anyhow::bail!("unsupported Sponge schematic version {}", v),
+    };
+
+    let mut palette = Vec::new();
+    for (state, index) in container.compound("Palette")? {
+        let index = index
+            .as_i32()
+            .ok_or_else(|| anyhow::anyhow!("palette entry {} is not an int", state))?;
+        let index = usize::try_from(index)
+            .map_err(|_| anyhow::anyhow!("palette entry {} has negative index {}", state, index))?;
+        if palette.len() <= index {
+            palette.resize(index + 1, None);
+        }
+        palette[index] = Block::from_state_string((0, 0, 0), state);
+    }
+
+    let data = container
+        .get(data_key)
+        .and_then(|t| t.as_byte_array())
+        .ok_or_else(|| anyhow::anyhow!("schematic has no {}", data_key))?;
+    let data = read_varints(data)?;
+    if data.len() < width * height * length {
+        anyhow::bail!("schematic has truncated {}", data_key);
+    }
+
+    let mut block_entities = HashMap::new();
+    for entity in container
+        .get(entities_key)
+        .and_then(|t| t.as_list())
+        .unwrap_or_default()
+    {
+        let pos = vec3(entity.get("Pos"));
+        // v3 moved the extra fields into Data
+        let data = entity.get("Data").unwrap_or(entity);
+        block_entities.insert(pos, data.clone());
+    }
+
+    let mut blocks = Vec::new();
+    for y in 0..height {
+        for z in 0..length {
+            for x in 0..width {
+                let index = x + z * width + y * width * length;
+                let Some(Some(block)) = palette.get(data[index]) else {
+                    continue;
+                };
+
+                let local = (x as i32, y as i32, z as i32);
+                blocks.push(Block {
+                    pos: (local.0 + offset.0, local.1 + offset.1, local.2 + offset.2),
+                    nbt: block_entities.remove(&local),
+                    ..block.clone()
+                });
+            }
+        }
+    }
+
+    Ok(blocks)
+}
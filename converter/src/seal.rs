@@ -0,0 +1,204 @@
+use crate::greedy;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A block position, signed since sealing goes around the outside of the build.
+pub type Cell = (i64, i64, i64);
+
+const NEIGHBORS: [Cell; 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+fn bounds(cells: impl Iterator<Item = Cell>) -> (Cell, Cell) {
+    cells.fold(
+        (
+            (i64::MAX, i64::MAX, i64::MAX),
+            (i64::MIN, i64::MIN, i64::MIN),
+        ),
+        |(min, max), c| {
+            (
+                (min.0.min(c.0), min.1.min(c.1), min.2.min(c.2)),
+                (max.0.max(c.0), max.1.max(c.1), max.2.max(c.2)),
+            )
+        },
+    )
+}
+
+/// Flood fills from outside the bounds through everything that isn't solid. If that reaches any
+/// of the targets, returns which one got reached first and the path there, starting outside.
+pub fn find_leak(solid: &HashSet<Cell>, targets: &[Cell]) -> Option<(usize, Vec<Cell>)> {
+    if targets.is_empty() {
+        return None;
+    }
+    let (min, max) = bounds(solid.iter().chain(targets).copied());
+    let (min, max) = (
+        (min.0 - 1, min.1 - 1, min.2 - 1),
+        (max.0 + 1, max.1 + 1, max.2 + 1),
+    );
+
+    let mut came_from = HashMap::from([(min, min)]);
+    let mut queue = VecDeque::from([min]);
+    while let Some(cell) = queue.pop_front() {
+        if let Some(target) = targets.iter().position(|t| *t == cell) {
+            let mut path = vec![cell];
+            while let Some(&previous) = came_from.get(path.last().unwrap()) {
+                if previous == *path.last().unwrap() {
+                    break;
+                }
+                path.push(previous);
+            }
+            path.reverse();
+            return Some((target, path));
+        }
+
+        for (dx, dy, dz) in NEIGHBORS {
+            let next = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+            let inside = (min.0..=max.0).contains(&next.0)
+                && (min.1..=max.1).contains(&next.1)
+                && (min.2..=max.2).contains(&next.2);
+            if inside && !solid.contains(&next) && !came_from.contains_key(&next) {
+                came_from.insert(next, cell);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+/// Cuts a path down to where it changes direction, which is all a pointfile needs.
+pub fn corners(path: &[Cell]) -> Vec<Cell> {
+    let mut corners = Vec::new();
+    for (i, cell) in path.iter().enumerate() {
+        let straight = i > 0 && i + 1 < path.len() && {
+            let (a, b) = (path[i - 1], path[i + 1]);
+            (b.0 - cell.0, b.1 - cell.1, b.2 - cell.2) == (cell.0 - a.0, cell.1 - a.1, cell.2 - a.2)
+        };
+        if !straight {
+            corners.push(*cell);
+        }
+    }
+    corners
+}
+
+/// Walls that follow the build's outline from above, with a floor under it and a ceiling one
+/// block over the tallest thing. Anything inside a column that has a block in it, or that's
+/// surrounded by those, ends up sealed in.
+pub fn hull(occupied: &HashSet<Cell>) -> HashSet<Cell> {
+    if occupied.is_empty() {
+        return HashSet::new();
+    }
+    let (min, max) = bounds(occupied.iter().copied());
+    let columns = occupied.iter().map(|c| (c.0, c.2)).collect::<HashSet<_>>();
+
+    // Whatever columns the outside can't get to are part of the footprint, courtyards included
+    let mut outside = HashSet::from([(min.0 - 1, min.2 - 1)]);
+    let mut stack = vec![(min.0 - 1, min.2 - 1)];
+    while let Some((x, z)) = stack.pop() {
+        for next in [(x + 1, z), (x - 1, z), (x, z + 1), (x, z - 1)] {
+            let inside = (min.0 - 1..=max.0 + 1).contains(&next.0)
+                && (min.2 - 1..=max.2 + 1).contains(&next.1);
+            if inside && !columns.contains(&next) && outside.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+
+    let mut cells = HashSet::new();
+    for x in min.0 - 1..=max.0 + 1 {
+        for z in min.2 - 1..=max.2 + 1 {
+            if !outside.contains(&(x, z)) {
+                cells.insert((x, min.1 - 1, z));
+                cells.insert((x, max.1 + 1, z));
+                continue;
+            }
+            let next_to_footprint =
+                [(x + 1, z), (x - 1, z), (x, z + 1), (x, z - 1)]
+                    .iter()
+                    .any(|c| {
+                        (min.0..=max.0).contains(&c.0)
+                            && (min.2..=max.2).contains(&c.1)
+                            && !outside.contains(c)
+                    });
+            if next_to_footprint {
+                cells.extend((min.1 - 1..=max.1 + 1).map(|y| (x, y, z)));
+            }
+        }
+    }
+    cells
+}
+
+/// A one block thick box around everything, `padding` blocks away from it.
+pub fn shell(occupied: &HashSet<Cell>, padding: i64) -> HashSet<Cell> {
+    if occupied.is_empty() {
+        return HashSet::new();
+    }
+    let (min, max) = bounds(occupied.iter().copied());
+    let (min, max) = (
+        (
+            min.0 - padding - 1,
+            min.1 - padding - 1,
+            min.2 - padding - 1,
+        ),
+        (
+            max.0 + padding + 1,
+            max.1 + padding + 1,
+            max.2 + padding + 1,
+        ),
+    );
+
+    let mut cells = HashSet::new();
+    for x in min.0..=max.0 {
+        for y in min.1..=max.1 {
+            for z in min.2..=max.2 {
+                if x == min.0 || x == max.0 || y == min.1 || y == max.1 || z == min.2 || z == max.2
+                {
+                    cells.insert((x, y, z));
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// Merges cells into as few boxes as greedy manages, as (min, max) pairs.
+pub fn boxes(cells: &HashSet<Cell>) -> Vec<(Cell, Cell)> {
+    if cells.is_empty() {
+        return Vec::new();
+    }
+    // The mesher only does unsigned positions
+    let (min, _) = bounds(cells.iter().copied());
+    let voxels = cells
+        .iter()
+        .map(|c| {
+            let pos = (
+                (c.0 - min.0) as usize,
+                (c.1 - min.1) as usize,
+                (c.2 - min.2) as usize,
+            );
+            (
+                pos,
+                greedy::Voxel::new("seal".to_string(), String::new()).with_overlap(true),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let directions = [
+        greedy::GreedyDirection::X,
+        greedy::GreedyDirection::Z,
+        greedy::GreedyDirection::Y,
+    ];
+    greedy::greedy(&voxels, &directions)
+        .into_iter()
+        .map(|b| {
+            let unshift = |p: (usize, usize, usize)| {
+                (p.0 as i64 + min.0, p.1 as i64 + min.1, p.2 as i64 + min.2)
+            };
+            (unshift(b.min), unshift(b.max))
+        })
+        .collect()
+}
@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+
+/// One convex piece of a block, as the fraction of the block it covers on each axis. Axes are
+/// Minecraft's: x east, y up, z south.
+pub type Part = ([f64; 3], [f64; 3]);
+
+fn pixels(from: [f64; 3], to: [f64; 3]) -> Part {
+    (from.map(|v| v / 16.), to.map(|v| v / 16.))
+}
+
+/// Turns a part clockwise seen from above, the same way blockstate `y` rotations go.
+fn turn(part: Part, degrees: i32) -> Part {
+    let (mut from, mut to) = part;
+    for _ in 0..degrees.rem_euclid(360) / 90 {
+        // East goes south: (x, z) -> (1 - z, x)
+        (from, to) = ([1. - to[2], from[1], from[0]], [1. - from[2], to[1], to[0]]);
+    }
+    (from, to)
+}
+
+fn upside_down((from, to): Part) -> Part {
+    ([from[0], 1. - to[1], from[2]], [to[0], 1. - from[1], to[2]])
+}
+
+fn stairs(props: &HashMap<String, String>) -> Vec<Part> {
+    // Straight and the right-hand shapes facing east, same as the vanilla models
+    let base = pixels([0., 0., 0.], [16., 8., 16.]);
+    let shape = props.get("shape").map(|s| s.as_str()).unwrap_or("straight");
+    let steps = match shape {
+        "inner_left" | "inner_right" => vec![
+            pixels([8., 8., 0.], [16., 16., 16.]),
+            pixels([0., 8., 8.], [8., 16., 16.]),
+        ],
+        "outer_left" | "outer_right" => vec![pixels([8., 8., 8.], [16., 16., 16.])],
+        _ => vec![pixels([8., 8., 0.], [16., 16., 16.])],
+    };
+
+    // Left is right turned a quarter back
+    let degrees = facing(props) + if shape.ends_with("_left") { 270 } else { 0 };
+    let top = props.get("half").is_some_and(|h| h == "top");
+
+    std::iter::once(base)
+        .chain(steps.into_iter().map(|step| turn(step, degrees)))
+        .map(|part| if top { upside_down(part) } else { part })
+        .collect()
+}
+
+/// Which sides a fence, wall or pane reaches out to, as the `turn` for each. Walls say `low` or
+/// `tall` rather than `true`, and `none` when they don't connect.
+fn connections(props: &HashMap<String, String>) -> Vec<(i32, &str)> {
+    [("north", 0), ("east", 90), ("south", 180), ("west", 270)]
+        .into_iter()
+        .filter_map(
+            |(side, degrees)| match props.get(side).map(|s| s.as_str()) {
+                None | Some("false") | Some("none") => None,
+                Some(how) => Some((degrees, how)),
+            },
+        )
+        .collect()
+}
+
+/// A post in the middle with arms going out to each connected side. Arms are given pointing
+/// north, and stop where the post starts so nothing overlaps.
+fn posts(post: Option<Part>, arms: Vec<(i32, Vec<Part>)>) -> Vec<Part> {
+    // Two arms in a straight line without a post are really just one long piece
+    if post.is_none() && arms.len() == 2 && arms[0].0 + 180 == arms[1].0 {
+        return arms[0]
+            .1
+            .iter()
+            .map(|&(from, to)| turn((from, [to[0], to[1], 1.]), arms[0].0))
+            .collect();
+    }
+
+    post.into_iter()
+        .chain(arms.into_iter().flat_map(|(degrees, parts)| {
+            parts.into_iter().map(move |(from, to)| {
+                let reach = post.map(|(from, _)| from[2]).unwrap_or(0.5);
+                turn((from, [to[0], to[1], reach]), degrees)
+            })
+        }))
+        .collect()
+}
+
+fn fence(props: &HashMap<String, String>) -> Vec<Part> {
+    let arms = connections(props)
+        .into_iter()
+        .map(|(degrees, _)| {
+            (
+                degrees,
+                vec![
+                    pixels([7., 12., 0.], [9., 15., 0.]),
+                    pixels([7., 6., 0.], [9., 9., 0.]),
+                ],
+            )
+        })
+        .collect();
+    posts(Some(pixels([6., 0., 6.], [10., 16., 10.])), arms)
+}
+
+fn wall(props: &HashMap<String, String>) -> Vec<Part> {
+    let arms = connections(props)
+        .into_iter()
+        .map(|(degrees, how)| {
+            let height = if how == "tall" { 16. } else { 14. };
+            (degrees, vec![pixels([5., 0., 0.], [11., height, 0.])])
+        })
+        .collect();
+    // Straight runs of wall skip the post unless there's something on top
+    let post = props.get("up").is_none_or(|up| up == "true");
+    posts(post.then(|| pixels([4., 0., 4.], [12., 16., 12.])), arms)
+}
+
+fn pane(props: &HashMap<String, String>) -> Vec<Part> {
+    let arms = connections(props)
+        .into_iter()
+        .map(|(degrees, _)| (degrees, vec![pixels([7., 0., 0.], [9., 16., 0.])]))
+        .collect::<Vec<_>>();
+    // The post is always there, but between two arms it's just part of the same sheet of glass
+    let straight = arms.len() == 2 && arms[0].0 + 180 == arms[1].0;
+    posts(
+        (!straight).then(|| pixels([7., 0., 7.], [9., 16., 9.])),
+        arms,
+    )
+}
+
+/// Doors lie along the side they were placed against, turned the same way as stairs. They're
+/// always built shut, opening is the door entity's job.
+fn door(props: &HashMap<String, String>) -> Vec<Part> {
+    vec![turn(pixels([0., 0., 0.], [3., 16., 16.]), facing(props))]
+}
+
+fn trapdoor(props: &HashMap<String, String>) -> Vec<Part> {
+    if props.get("half").is_some_and(|h| h == "top") {
+        vec![pixels([0., 13., 0.], [16., 16., 16.])]
+    } else {
+        vec![pixels([0., 0., 0.], [16., 3., 16.])]
+    }
+}
+
+/// Turn for blocks facing east, like stairs and doors.
+fn facing(props: &HashMap<String, String>) -> i32 {
+    match props.get("facing").map(|s| s.as_str()) {
+        Some("south") => 90,
+        Some("west") => 180,
+        Some("north") => 270,
+        _ => 0,
+    }
+}
+
+/// Turn for blocks that face north unturned, like trapdoors and ladders.
+fn facing_north(props: &HashMap<String, String>) -> i32 {
+    facing(props) + 90
+}
+
+/// A sheet `thickness` pixels thick against the side of the block `degrees` around from north.
+fn plate(degrees: i32, thickness: f64) -> Part {
+    turn(pixels([0., 0., 0.], [16., 16., thickness]), degrees)
+}
+
+/// Vines and the other plants you can climb, which only get to be ladders when asked.
+pub fn is_vine(id: &str) -> bool {
+    [
+        "vine",
+        "scaffolding",
+        "twisting_vines",
+        "twisting_vines_plant",
+        "weeping_vines",
+        "weeping_vines_plant",
+    ]
+    .contains(&id)
+}
+
+/// The space in a block you can climb, for a `func_ladder`. It sticks out further than the ladder
+/// itself so players grab it before walking into it.
+pub fn climb_volume(id: &str, props: &HashMap<String, String>) -> Option<Vec<Part>> {
+    if id == "ladder" {
+        // Ladders sit against the wall behind them, the opposite way to where they face
+        return Some(vec![plate(facing_north(props) + 180, 4.)]);
+    }
+    if id == "vine" {
+        return Some(
+            connections(props)
+                .into_iter()
+                .map(|(degrees, _)| plate(degrees, 4.))
+                .collect(),
+        );
+    }
+    // Everything else you climb the whole block of
+    is_vine(id).then(|| vec![([0.; 3], [1.; 3])])
+}
+
+/// Doors and trapdoors, which turn into moving entities instead of world brushes.
+pub fn is_door(id: &str) -> bool {
+    id.ends_with("_door") || id.ends_with("_trapdoor")
+}
+
+/// Where a door or trapdoor swings from: a point on the hinge in block fractions, and the axis it
+/// turns around to open (x east, y up, z south), going the right-handed way.
+pub fn hinge(id: &str, props: &HashMap<String, String>) -> Option<([f64; 3], [f64; 3])> {
+    let point = |p: [f64; 3], degrees: i32| turn((p, p), degrees).0;
+    if id.ends_with("_trapdoor") {
+        // Hinged along the edge opposite where it faces, flipping up off the floor or down off the
+        // ceiling
+        let degrees = facing_north(props);
+        let top = props.get("half").is_some_and(|h| h == "top");
+        let y = if top { 1. } else { 0. };
+        // Turning the axis as a point off the middle of the block
+        let axis = point([if top { 0. } else { 1. }, 0., 0.5], degrees);
+        return Some((
+            point([0.5, y, 1.], degrees),
+            [axis[0] - 0.5, 0., axis[2] - 0.5].map(|v| v * 2.),
+        ));
+    }
+    if id.ends_with("_door") {
+        // Facing east the door's along the west side, and the hinge is on the player's left (north)
+        // or right (south) as they walk through
+        let right = props.get("hinge").is_some_and(|h| h == "right");
+        let z = if right { 1. } else { 0. };
+        let up = if right { -1. } else { 1. };
+        return Some((point([0., 0.5, z], facing(props)), [0., up, 0.]));
+    }
+    None
+}
+
+/// Glass panes and iron bars, which are see-through and end up in their own entity.
+pub fn is_pane(id: &str) -> bool {
+    id == "glass_pane" || id.ends_with("_glass_pane") || id == "iron_bars"
+}
+
+/// The pieces of a block that isn't a full cube, or None if it is one.
+pub fn parts(id: &str, props: &HashMap<String, String>) -> Option<Vec<Part>> {
+    let height = |pixels: f64| Some(vec![([0., 0., 0.], [1., pixels / 16., 1.])]);
+
+    if id.ends_with("_slab") {
+        return match props.get("type").map(|s| s.as_str()) {
+            Some("top") => Some(vec![([0., 0.5, 0.], [1., 1., 1.])]),
+            Some("double") => None,
+            _ => height(8.),
+        };
+    }
+    if id.ends_with("_stairs") {
+        return Some(stairs(props));
+    }
+    if id.ends_with("_trapdoor") {
+        return Some(trapdoor(props));
+    }
+    if id.ends_with("_door") {
+        return Some(door(props));
+    }
+    if id == "water" || id == "lava" {
+        // Sources sit a bit under the top of the block and flowing fluid drops off from there.
+        // Falling fluid fills the whole thing
+        let level = props.get("level").and_then(|l| l.parse::<u32>().ok());
+        return match level.unwrap_or(0) {
+            8.. => None,
+            level => height(((8 - level) as f64 * 16. / 9.).round()),
+        };
+    }
+    if id == "ladder" {
+        return Some(vec![plate(facing_north(props) + 180, 1.)]);
+    }
+    if id == "vine" {
+        let sides = connections(props);
+        // Just hanging off something above, draw it against the ceiling
+        if sides.is_empty() {
+            return Some(vec![pixels([0., 15., 0.], [16., 16., 16.])]);
+        }
+        return Some(
+            sides
+                .into_iter()
+                .map(|(degrees, _)| plate(degrees, 1.))
+                .collect(),
+        );
+    }
+    if id.ends_with("_fence") {
+        return Some(fence(props));
+    }
+    if id.ends_with("_wall") {
+        return Some(wall(props));
+    }
+    if is_pane(id) {
+        return Some(pane(props));
+    }
+    if id.ends_with("_carpet") {
+        return height(1.);
+    }
+    if id == "snow" {
+        let layers = props.get("layers").and_then(|l| l.parse::<u32>().ok());
+        return match layers.unwrap_or(1) {
+            8.. => None,
+            layers => height(layers as f64 * 2.),
+        };
+    }
+    if id == "dirt_path" || id == "farmland" {
+        return height(15.);
+    }
+    None
+}
+
+/// Which axes a box of the block can be stretched along and still be the same shape: the ones
+/// every part covers all the way across.
+pub fn stretch_axes(parts: &[Part]) -> [bool; 3] {
+    [0, 1, 2].map(|axis| {
+        parts
+            .iter()
+            .all(|(from, to)| from[axis] == 0. && to[axis] == 1.)
+    })
+}
+
+/// Blocks to borrow textures from when a shaped block has none of its own: slabs, stairs, fences
+/// and walls are named after what they're made of, give or take a plural or `_planks`.
+pub fn texture_sources(id: &str, props: &HashMap<String, String>) -> Vec<String> {
+    if id.ends_with("_door") {
+        let half = match props.get("half").map(|s| s.as_str()) {
+            Some("upper") => "top",
+            _ => "bottom",
+        };
+        return vec![format!("{}_{}", id, half)];
+    }
+    if let Some(color) = id.strip_suffix("_carpet") {
+        return match color {
+            "moss" | "pale_moss" => vec![format!("{}_block", color)],
+            _ => vec![format!("{}_wool", color)],
+        };
+    }
+    if let Some(glass) = id.strip_suffix("_pane") {
+        return vec![glass.to_string()];
+    }
+    let Some(base) = ["_slab", "_stairs", "_fence", "_wall"]
+        .iter()
+        .find_map(|suffix| id.strip_suffix(suffix))
+    else {
+        return Vec::new();
+    };
+    vec![
+        base.to_string(),
+        format!("{}s", base),
+        format!("{}_planks", base),
+        format!("{}_block", base),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn middle((from, to): Part) -> [f64; 3] {
+        [0, 1, 2].map(|i| (from[i] + to[i]) / 2.)
+    }
+
+    /// Where the middle of a closed block ends up once it's swung a quarter turn around its hinge,
+    /// right-handed around the axis.
+    fn swing(id: &str, props: &HashMap<String, String>) -> [f64; 3] {
+        let (point, axis) = hinge(id, props).unwrap();
+        let length = axis.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let a = axis.map(|v| v / length);
+        let v = [0, 1, 2].map(|i| middle(parts(id, props).unwrap()[0])[i] - point[i]);
+        let along = a[0] * v[0] + a[1] * v[1] + a[2] * v[2];
+        let cross = [
+            a[1] * v[2] - a[2] * v[1],
+            a[2] * v[0] - a[0] * v[2],
+            a[0] * v[1] - a[1] * v[0],
+        ];
+        [0, 1, 2].map(|i| point[i] + cross[i] + a[i] * along)
+    }
+
+    /// Swinging around an edge rather than the middle of the plank lands it a plank's thickness
+    /// off from the vanilla model, going the wrong way lands it a whole block off.
+    fn assert_near(got: [f64; 3], want: [f64; 3], what: &str) {
+        let distance = (0..3)
+            .map(|i| (got[i] - want[i]).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        assert!(distance <= 3. / 16. + 1e-9, "{what}: {got:?} vs {want:?}");
+    }
+
+    #[test]
+    fn doors_hinge_on_the_left_or_right() {
+        let left = props(&[("facing", "east"), ("hinge", "left")]);
+        assert_eq!(
+            hinge("oak_door", &left),
+            Some(([0., 0.5, 0.], [0., 1., 0.]))
+        );
+        let right = props(&[("facing", "east"), ("hinge", "right")]);
+        assert_eq!(
+            hinge("oak_door", &right),
+            Some(([0., 0.5, 1.], [0., -1., 0.]))
+        );
+
+        // Facing south puts the door on the north side, with the left hinge in the east corner
+        let south = props(&[("facing", "south"), ("hinge", "left")]);
+        assert_eq!(
+            hinge("oak_door", &south),
+            Some(([1., 0.5, 0.], [0., 1., 0.]))
+        );
+
+        for side in ["east", "south", "west", "north"] {
+            for hand in ["left", "right"] {
+                let props = props(&[("facing", side), ("hinge", hand)]);
+                // Vanilla's open doors are the closed one turned a quarter further, towards the
+                // hinge
+                let extra = if hand == "right" { 270 } else { 90 };
+                let open = turn(pixels([0., 0., 0.], [3., 16., 16.]), facing(&props) + extra);
+                assert_near(
+                    swing("oak_door", &props),
+                    middle(open),
+                    &format!("{side} {hand}"),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trapdoors_hinge_on_the_far_edge() {
+        let bottom = props(&[("facing", "north"), ("half", "bottom")]);
+        assert_eq!(
+            hinge("oak_trapdoor", &bottom),
+            Some(([0.5, 0., 1.], [1., 0., 0.]))
+        );
+        let top = props(&[("facing", "north"), ("half", "top")]);
+        assert_eq!(
+            hinge("oak_trapdoor", &top),
+            Some(([0.5, 1., 1.], [-1., 0., 0.]))
+        );
+
+        for side in ["east", "south", "west", "north"] {
+            for half in ["bottom", "top"] {
+                let props = props(&[("facing", side), ("half", half)]);
+                // Open trapdoors stand against the side opposite the way they face, top or bottom
+                let open = turn(pixels([0., 0., 13.], [16., 16., 16.]), facing_north(&props));
+                assert_near(
+                    swing("oak_trapdoor", &props),
+                    middle(open),
+                    &format!("{side} {half}"),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_doors_have_hinges() {
+        assert_eq!(hinge("oak_stairs", &props(&[("facing", "east")])), None);
+    }
+
+    /// Which of the eight half-block cells a set of parts fills. Stairs are all on half-block
+    /// lines, so this tells two shapes apart however they're split into boxes.
+    fn cells(parts: &[Part]) -> Vec<[usize; 3]> {
+        let mut cells = Vec::new();
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    let point = [x, y, z].map(|v| v as f64 * 0.5 + 0.25);
+                    if parts
+                        .iter()
+                        .any(|(from, to)| (0..3).all(|i| from[i] < point[i] && point[i] < to[i]))
+                    {
+                        cells.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn turning_goes_clockwise_from_above() {
+        let east = pixels([12., 0., 0.], [16., 16., 16.]);
+        assert_eq!(turn(east, 0), east);
+        assert_eq!(turn(east, 90), pixels([0., 0., 12.], [16., 16., 16.]));
+        assert_eq!(turn(east, 180), pixels([0., 0., 0.], [4., 16., 16.]));
+        assert_eq!(turn(east, 270), pixels([0., 0., 0.], [16., 16., 4.]));
+        assert_eq!(turn(east, 360), east);
+        assert_eq!(turn(east, -90), turn(east, 270));
+
+        let corner = pixels([8., 2., 0.], [16., 6., 4.]);
+        assert_eq!(turn(corner, 90), pixels([12., 2., 8.], [16., 6., 16.]));
+    }
+
+    #[test]
+    fn stairs_match_vanilla() {
+        // The vanilla models' boxes
+        let straight = vec![
+            pixels([0., 0., 0.], [16., 8., 16.]),
+            pixels([8., 8., 0.], [16., 16., 16.]),
+        ];
+        let inner = vec![
+            pixels([0., 0., 0.], [16., 8., 16.]),
+            pixels([8., 8., 0.], [16., 16., 16.]),
+            pixels([0., 8., 8.], [8., 16., 16.]),
+        ];
+        let outer = vec![
+            pixels([0., 0., 0.], [16., 8., 16.]),
+            pixels([8., 8., 8.], [16., 16., 16.]),
+        ];
+
+        // The blockstate file's y rotations for straight, inner_left, inner_right, outer_left and
+        // outer_right. Top halves are also turned x=180, which flips them north to south as well
+        let shapes = [
+            ("straight", &straight),
+            ("inner_left", &inner),
+            ("inner_right", &inner),
+            ("outer_left", &outer),
+            ("outer_right", &outer),
+        ];
+        let rotations = [
+            ("east", "bottom", [0, 270, 0, 270, 0]),
+            ("east", "top", [0, 0, 90, 0, 90]),
+            ("south", "bottom", [90, 0, 90, 0, 90]),
+            ("south", "top", [90, 90, 180, 90, 180]),
+            ("west", "bottom", [180, 90, 180, 90, 180]),
+            ("west", "top", [180, 180, 270, 180, 270]),
+            ("north", "bottom", [270, 180, 270, 180, 270]),
+            ("north", "top", [270, 270, 0, 270, 0]),
+        ];
+
+        for (side, half, ys) in rotations {
+            for ((shape, model), y) in shapes.iter().zip(ys) {
+                let want = model
+                    .iter()
+                    .map(|&(from, to)| {
+                        let part = if half == "top" {
+                            (
+                                [from[0], 1. - to[1], 1. - to[2]],
+                                [to[0], 1. - from[1], 1. - from[2]],
+                            )
+                        } else {
+                            (from, to)
+                        };
+                        turn(part, y)
+                    })
+                    .collect::<Vec<_>>();
+                let props = props(&[("facing", side), ("half", half), ("shape", shape)]);
+                let got = parts("oak_stairs", &props).unwrap();
+                assert_eq!(cells(&got), cells(&want), "{side} {half} {shape}");
+            }
+        }
+    }
+
+    #[test]
+    fn straight_stairs_stretch_along_their_width() {
+        for (side, along) in [
+            ("east", [false, false, true]),
+            ("west", [false, false, true]),
+            ("south", [true, false, false]),
+            ("north", [true, false, false]),
+        ] {
+            for half in ["bottom", "top"] {
+                let props = props(&[("facing", side), ("half", half)]);
+                let parts = parts("oak_stairs", &props).unwrap();
+                assert_eq!(stretch_axes(&parts), along, "{side} {half}");
+            }
+        }
+
+        // Corners don't line up with anything next to them
+        for shape in ["inner_left", "inner_right", "outer_left", "outer_right"] {
+            let props = props(&[("facing", "east"), ("shape", shape)]);
+            let parts = parts("oak_stairs", &props).unwrap();
+            assert_eq!(stretch_axes(&parts), [false; 3], "{shape}");
+        }
+    }
+
+    #[test]
+    fn stretching_needs_every_part_to_reach_across() {
+        let slab = parts("oak_slab", &props(&[])).unwrap();
+        assert_eq!(stretch_axes(&slab), [true, false, true]);
+
+        // Flat against the south side
+        let ladder = parts("ladder", &props(&[("facing", "north")])).unwrap();
+        assert_eq!(stretch_axes(&ladder), [true, true, false]);
+
+        let fence = parts("oak_fence", &props(&[("east", "true"), ("west", "true")])).unwrap();
+        assert_eq!(stretch_axes(&fence), [false; 3]);
+    }
+}
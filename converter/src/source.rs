@@ -0,0 +1,215 @@
+use crate::{util, vmf};
+use image::RgbaImage;
+use std::path::Path;
+
+// VTF flags, see https://developer.valvesoftware.com/wiki/VTF_(Valve_Texture_Format)
+const TEXTUREFLAGS_POINTSAMPLE: u32 = 0x1;
+const TEXTUREFLAGS_ONEBITALPHA: u32 = 0x1000;
+const TEXTUREFLAGS_EIGHTBITALPHA: u32 = 0x2000;
+const IMAGE_FORMAT_RGBA8888: u32 = 0;
+
+/// Turns a GoldSrc texture name into a Source material path.
+pub fn material(texture: &str) -> String {
+    match texture {
+        "SKY" => "tools/toolsskybox".to_string(),
+        "CLIP" => "tools/toolsclip".to_string(),
+        "NULL" => "tools/toolsnodraw".to_string(),
+        "SKIP" => "tools/toolsskip".to_string(),
+        "ORIGIN" => "tools/toolsorigin".to_string(),
+        "AAATRIGGER" => "tools/toolstrigger".to_string(),
+        "MISSING" => "dev/dev_measuregeneric01".to_string(),
+        // Source doesn't care about the masked/animated prefixes
+        _ => format!(
+            "minecraft/{}",
+            texture.trim_start_matches(['{', '!']).to_lowercase()
+        ),
+    }
+}
+
+/// Swaps the GoldSrc-only entities (and ZHLT keys) for what Source uses instead. Returns `None`
+/// for entities Source has no use for.
+pub fn convert_entity(mut entity: vmf::Entity) -> Option<vmf::Entity> {
+    entity.properties.retain(|(k, _)| !k.starts_with("zhlt_"));
+
+    match entity.classname.as_str() {
+        // Texture lights live in lights.rad instead
+        "info_texlights" => return None,
+        "func_illusionary" => {
+            entity.classname = "func_brush".to_string();
+            // Masking comes from $alphatest in the material now
+            entity
+                .properties
+                .retain(|(k, _)| k != "rendermode" && k != "renderamt");
+            entity.set("Solidity", "1");
+        }
+        "func_wall" => {
+            entity.classname = "func_brush".to_string();
+            entity
+                .properties
+                .retain(|(k, _)| k != "rendermode" && k != "renderamt");
+            entity.set("Solidity", "2");
+        }
+        "func_ladder" => {
+            // Source ladders are just brushes with the ladder tool texture
+            entity.classname = "func_detail".to_string();
+            for side in entity.solids.iter_mut().flat_map(|s| s.sides.iter_mut()) {
+                side.material = "tools/toolsinvisibleladder".to_string();
+            }
+        }
+        "func_water" => {
+            // Proper water is a material thing in Source, so this just keeps it see-through and
+            // walkable
+            entity.classname = "func_brush".to_string();
+            entity
+                .properties
+                .retain(|(k, _)| k != "skin" && k != "WaveHeight");
+            entity.set("Solidity", "1");
+        }
+        "func_breakable" => {
+            // Same deal, translucency is the material's job
+            entity
+                .properties
+                .retain(|(k, _)| k != "rendermode" && k != "renderamt");
+        }
+        "env_sprite" => {
+            // GoldSrc abuses env_sprite to place static models, but they're GoldSrc .mdls Source
+            // can't load, so they have to be redone by hand
+            if let Some((_, model)) = entity
+                .properties
+                .iter()
+                .find(|(k, v)| k == "model" && v.ends_with(".mdl"))
+            {
+                println!("Leaving out {}, Source can't load GoldSrc models", model);
+                return None;
+            }
+        }
+        "light" => {
+            // The plain "light" key is a VHLT thing, _light is what Source reads
+            entity.properties.retain(|(k, _)| k != "light");
+        }
+        _ => {}
+    }
+
+    Some(entity)
+}
+
+fn load_texture(textures_dir: &str, name: &str) -> anyhow::Result<Option<RgbaImage>> {
+    // The PNG is named after the GoldSrc texture, with or without its masked prefix
+    match util::load_png(textures_dir, &format!("{{{}", name))? {
+        Some(image) => Ok(Some(image)),
+        None => util::load_png(textures_dir, name),
+    }
+}
+
+/// Uncompressed RGBA8888 VTF 7.2 with a full mip chain.
+fn build_vtf(image: &RgbaImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mip_count = 32 - width.max(height).leading_zeros();
+
+    let has_partial_alpha = image.pixels().any(|p| p[3] > 0 && p[3] < 255);
+    let has_alpha = image.pixels().any(|p| p[3] < 255);
+    let mut flags = TEXTUREFLAGS_POINTSAMPLE;
+    if has_partial_alpha {
+        flags |= TEXTUREFLAGS_EIGHTBITALPHA;
+    } else if has_alpha {
+        flags |= TEXTUREFLAGS_ONEBITALPHA;
+    }
+
+    let pixel_count = (width * height) as f32;
+    let mut reflectivity = [0f32; 3];
+    for pixel in image.pixels() {
+        for (channel, r) in reflectivity.iter_mut().enumerate() {
+            *r += pixel[channel] as f32 / 255. / pixel_count;
+        }
+    }
+
+    let mut vtf = Vec::new();
+    vtf.extend_from_slice(b"VTF\0");
+    vtf.extend_from_slice(&7u32.to_le_bytes());
+    vtf.extend_from_slice(&2u32.to_le_bytes());
+    vtf.extend_from_slice(&80u32.to_le_bytes());
+    vtf.extend_from_slice(&(width as u16).to_le_bytes());
+    vtf.extend_from_slice(&(height as u16).to_le_bytes());
+    vtf.extend_from_slice(&flags.to_le_bytes());
+    vtf.extend_from_slice(&1u16.to_le_bytes()); // frames
+    vtf.extend_from_slice(&0u16.to_le_bytes()); // first frame
+    vtf.extend_from_slice(&[0; 4]);
+    for r in reflectivity {
+        vtf.extend_from_slice(&r.to_le_bytes());
+    }
+    vtf.extend_from_slice(&[0; 4]);
+    vtf.extend_from_slice(&1f32.to_le_bytes()); // bumpmap scale
+    vtf.extend_from_slice(&IMAGE_FORMAT_RGBA8888.to_le_bytes());
+    vtf.push(mip_count as u8);
+    vtf.extend_from_slice(&u32::MAX.to_le_bytes()); // no low res image
+    vtf.push(0);
+    vtf.push(0);
+    vtf.extend_from_slice(&1u16.to_le_bytes()); // depth
+    vtf.resize(80, 0);
+
+    // Smallest mip first
+    for mip in (0..mip_count).rev() {
+        let mip_width = (width >> mip).max(1);
+        let mip_height = (height >> mip).max(1);
+        if mip == 0 {
+            vtf.extend_from_slice(image.as_raw());
+        } else {
+            let scaled = image::imageops::resize(
+                image,
+                mip_width,
+                mip_height,
+                image::imageops::FilterType::Triangle,
+            );
+            vtf.extend_from_slice(scaled.as_raw());
+        }
+    }
+
+    vtf
+}
+
+fn build_vmt(material: &str, image: &RgbaImage) -> String {
+    let mut vmt = format!(
+        "\"LightmappedGeneric\"\n{{\n  \"$basetexture\" \"{}\"\n",
+        material
+    );
+    // Glass-like textures blend, cutouts like leaves just clip
+    if image.pixels().any(|p| p[3] > 0 && p[3] < 255) {
+        vmt += "  \"$translucent\" \"1\"\n";
+    } else if image.pixels().any(|p| p[3] < 255) {
+        vmt += "  \"$alphatest\" \"1\"\n";
+    }
+    vmt += "}\n";
+    vmt
+}
+
+/// Writes a VMT and VTF under `out_dir` for every `minecraft/` material the map uses, from the
+/// PNGs in `textures_dir`. Returns the materials that had no PNG.
+pub fn write_materials(
+    vmf: &vmf::Vmf,
+    textures_dir: &str,
+    out_dir: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mut materials = vmf
+        .sides()
+        .map(|s| s.material.as_str())
+        .filter(|m| m.starts_with("minecraft/"))
+        .collect::<Vec<_>>();
+    materials.sort();
+    materials.dedup();
+
+    let mut missing = Vec::new();
+    for material in materials {
+        let name = material.trim_start_matches("minecraft/");
+        let Some(image) = load_texture(textures_dir, name)? else {
+            missing.push(material.to_string());
+            continue;
+        };
+
+        let path = Path::new(out_dir).join(material);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path.with_extension("vmt"), build_vmt(material, &image))?;
+        std::fs::write(path.with_extension("vtf"), build_vtf(&image))?;
+    }
+
+    Ok(missing)
+}
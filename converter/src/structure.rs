@@ -0,0 +1,51 @@
+use crate::{nbt, Block};
+
+/// Loads a structure block export. Structures with several `palettes` (shipwrecks and the like)
+/// are all the same shape with different materials, so `palette` picks which one to use.
+pub fn load(path: &str, palette: usize) -> anyhow::Result<Vec<Block>> {
+    let root = nbt::read_file(path)?;
+
+    let palette = match root.get("palettes").and_then(|p| p.as_list()) {
+        Some(palettes) => palettes
+            .get(palette)
+            .and_then(|p| p.as_list())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "structure only has {} palettes, wanted {}",
+                    palettes.len(),
+                    palette
+                )
+            })?,
+        None => root.list("palette")?,
+    };
+    let palette = palette
+        .iter()
+        .map(|state| Block::from_nbt_state((0, 0, 0), state))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut blocks = Vec::new();
+    for block in root.list("blocks")? {
+        let state = block.int("state")? as usize;
+        let pos = block
+            .list("pos")?
+            .iter()
+            .filter_map(|p| p.as_i32())
+            .collect::<Vec<_>>();
+        let [x, y, z] = pos[..] else {
+            anyhow::bail!("structure block has a malformed pos");
+        };
+
+        // Structure void isn't stored at all, and air comes out of the palette as None
+        let Some(Some(palette_block)) = palette.get(state) else {
+            continue;
+        };
+
+        blocks.push(Block {
+            pos: (x, y, z),
+            nbt: block.get("nbt").cloned(),
+            ..palette_block.clone()
+        });
+    }
+
+    Ok(blocks)
+}
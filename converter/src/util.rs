@@ -1,6 +1,8 @@
+use crate::vmf::Vec3;
+use image::RgbaImage;
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Face {
     South = 0,
     East = 1,
@@ -10,6 +12,89 @@ pub enum Face {
     Top = 5,
 }
 
+impl Face {
+    /// In side order, so `Face::ALL[i] as usize == i`.
+    pub const ALL: [Face; 6] = [
+        Face::South,
+        Face::East,
+        Face::North,
+        Face::West,
+        Face::Bottom,
+        Face::Top,
+    ];
+
+    /// Outward normal as (east, north, up), which is also Hammer's axes unless the transform
+    /// turns the map.
+    pub fn normal(self) -> Vec3 {
+        match self {
+            Face::South => (0., -1., 0.),
+            Face::East => (1., 0., 0.),
+            Face::North => (0., 1., 0.),
+            Face::West => (-1., 0., 0.),
+            Face::Bottom => (0., 0., -1.),
+            Face::Top => (0., 0., 1.),
+        }
+    }
+
+    pub fn from_normal(normal: Vec3) -> Face {
+        Face::ALL
+            .into_iter()
+            .find(|f| f.normal() == normal)
+            .unwrap_or(Face::Top)
+    }
+
+    /// Texture U and V axes for an unrotated block, matching Minecraft's default face UVs: the
+    /// texture is upright on the sides and north is up on the top.
+    pub fn texture_axes(self) -> (Vec3, Vec3) {
+        let down = (0., 0., -1.);
+        match self {
+            Face::South => ((1., 0., 0.), down),
+            Face::East => ((0., 1., 0.), down),
+            Face::North => ((-1., 0., 0.), down),
+            Face::West => ((0., -1., 0.), down),
+            Face::Bottom => ((1., 0., 0.), (0., 1., 0.)),
+            Face::Top => ((1., 0., 0.), (0., -1., 0.)),
+        }
+    }
+}
+
+/// A blockstate-style rotation: `x` degrees around the X axis, then `y` around the vertical,
+/// in steps of 90.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Rotation {
+    pub fn vector(self, v: Vec3) -> Vec3 {
+        let mut v = v;
+        // North goes down, up goes north
+        for _ in 0..self.x.rem_euclid(360) / 90 {
+            v = (v.0, v.2, -v.1);
+        }
+        // North goes east, clockwise seen from above
+        for _ in 0..self.y.rem_euclid(360) / 90 {
+            v = (v.1, -v.0, v.2);
+        }
+        // Keep -0 out of the output
+        (v.0 + 0., v.1 + 0., v.2 + 0.)
+    }
+
+    /// Where a face of the unrotated block ends up.
+    pub fn face(self, face: Face) -> Face {
+        Face::from_normal(self.vector(face.normal()))
+    }
+
+    /// Which face of the unrotated block ends up facing `face`.
+    pub fn source_face(self, face: Face) -> Face {
+        Face::ALL
+            .into_iter()
+            .find(|f| self.face(*f) == face)
+            .unwrap_or(face)
+    }
+}
+
 pub fn parse_properties(props: &str) -> HashMap<String, String> {
     // remove []
     let props = props.trim_matches(|c| c == '[' || c == ']');
@@ -28,3 +113,48 @@ pub fn parse_properties(props: &str) -> HashMap<String, String> {
 
     properties
 }
+
+/// Reads entry `index` from a tightly packed long array, where an entry can straddle two longs
+/// (Litematica, and chunks before 1.16).
+pub fn packed_value(states: &[i64], bits: usize, index: usize) -> usize {
+    let mask = (1u64 << bits) - 1;
+    let start_bit = index * bits;
+    let start_long = start_bit / 64;
+    let end_long = ((index + 1) * bits - 1) / 64;
+    let offset = start_bit % 64;
+
+    let value = if start_long == end_long {
+        (states[start_long] as u64) >> offset
+    } else {
+        ((states[start_long] as u64) >> offset) | ((states[end_long] as u64) << (64 - offset))
+    };
+    (value & mask) as usize
+}
+
+/// Reads entry `index` from a padded long array, where each long holds as many whole entries as
+/// fit and the leftover bits are unused (chunks since 1.16).
+pub fn padded_value(states: &[i64], bits: usize, index: usize) -> usize {
+    let per_long = 64 / bits;
+    let value = (states[index / per_long] as u64) >> ((index % per_long) * bits);
+    (value & ((1u64 << bits) - 1)) as usize
+}
+
+/// Where the PNG for a texture lives: `<dir>/<name>.png`.
+pub fn png_path(dir: &str, name: &str) -> std::path::PathBuf {
+    std::path::Path::new(dir).join(format!("{}.png", name))
+}
+
+/// Loads `<dir>/<name>.png`, if it exists. Animated textures are frames stacked vertically, so
+/// those get cropped to the first one.
+pub fn load_png(dir: &str, name: &str) -> anyhow::Result<Option<RgbaImage>> {
+    let path = png_path(dir, name);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut image = image::open(path)?.to_rgba8();
+    if image.height() > image.width() && image.height() % image.width() == 0 {
+        image = image::imageops::crop_imm(&image, 0, 0, image.width(), image.width()).to_image();
+    }
+    Ok(Some(image))
+}
@@ -0,0 +1,955 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::fmt;
+
+pub type Vec3 = (f64, f64, f64);
+pub type Color = (u8, u8, u8);
+
+/// A raw KeyValues block. Anything the typed model doesn't know about (hidden solids, groups,
+/// entity connections...) is kept as one of these so it survives a round trip.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub properties: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut String> {
+        self.properties
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    pub editor_version: u32,
+    pub editor_build: u32,
+    pub map_version: u32,
+    pub format_version: u32,
+    pub prefab: bool,
+}
+
+impl Default for VersionInfo {
+    fn default() -> Self {
+        Self {
+            editor_version: 400,
+            editor_build: 2959,
+            map_version: 1,
+            format_version: 100,
+            prefab: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ViewSettings {
+    pub snap_to_grid: bool,
+    pub show_grid: bool,
+    pub show_3d_grid: bool,
+    pub grid_spacing: u32,
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        Self {
+            snap_to_grid: true,
+            show_grid: true,
+            show_3d_grid: false,
+            grid_spacing: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VisGroup {
+    pub name: String,
+    pub id: u32,
+    pub color: Color,
+    pub visible: bool,
+    pub children: Vec<VisGroup>,
+}
+
+impl VisGroup {
+    pub fn new(name: &str, id: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            id,
+            color: (255, 255, 255),
+            visible: true,
+            children: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Editor {
+    pub color: Color,
+    pub visgroup_id: Option<u32>,
+    pub visgroup_shown: bool,
+    /// Everything else Hammer keeps in here (groupid, comments, logicalpos, more visgroupids...).
+    pub extra: Vec<(String, String)>,
+}
+
+impl Editor {
+    pub fn new(visgroup_id: u32) -> Self {
+        Self {
+            visgroup_id: Some(visgroup_id),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            color: (0, 255, 0),
+            visgroup_id: None,
+            visgroup_shown: true,
+            extra: Vec::new(),
+        }
+    }
+}
+
+/// One of `uaxis`/`vaxis`: `[x y z offset] scale`.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureAxis {
+    pub axis: Vec3,
+    pub offset: f64,
+    pub scale: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Side {
+    /// Bottom left, top left, top right, as seen from outside the brush.
+    pub plane: [Vec3; 3],
+    pub material: String,
+    pub uaxis: TextureAxis,
+    pub vaxis: TextureAxis,
+    pub rotation: f64,
+    pub lightmap_scale: u32,
+    pub smoothing_groups: u32,
+    /// Child blocks like `dispinfo`.
+    pub extra: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solid {
+    pub sides: Vec<Side>,
+    pub editor: Editor,
+    pub extra: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub classname: String,
+    pub properties: Vec<(String, String)>,
+    pub solids: Vec<Solid>,
+    pub editor: Editor,
+    pub extra: Vec<Node>,
+}
+
+impl Entity {
+    pub fn new(classname: &str) -> Self {
+        Self {
+            classname: classname.to_string(),
+            properties: Vec::new(),
+            solids: Vec::new(),
+            editor: Editor::default(),
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl ToString) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    pub fn with_origin(self, origin: Vec3) -> Self {
+        self.with("origin", format!("{} {} {}", origin.0, origin.1, origin.2))
+    }
+
+    pub fn with_editor(mut self, editor: Editor) -> Self {
+        self.editor = editor;
+        self
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.properties.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.properties.push((key.to_string(), value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct World {
+    pub properties: Vec<(String, String)>,
+    pub solids: Vec<Solid>,
+    pub extra: Vec<Node>,
+}
+
+impl World {
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.properties.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.properties.push((key.to_string(), value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: Vec3,
+    pub look: Vec3,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cameras {
+    pub active: i32,
+    pub cameras: Vec<Camera>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cordon {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vmf {
+    pub version_info: VersionInfo,
+    pub visgroups: Vec<VisGroup>,
+    pub view_settings: ViewSettings,
+    pub world: World,
+    pub entities: Vec<Entity>,
+    pub cameras: Cameras,
+    pub cordon: Cordon,
+    pub extra: Vec<Node>,
+}
+
+impl Vmf {
+    /// Swaps out everything in `visgroups` for the same content from `generated`, leaving the
+    /// rest of the (hand-edited) map alone. Brush entities count as generated when the entity
+    /// itself is in one of the visgroups.
+    pub fn replace_visgroups(&mut self, generated: Vmf, visgroups: &[u32]) {
+        let owned = |editor: &Editor| {
+            editor
+                .visgroup_id
+                .map(|id| visgroups.contains(&id))
+                .unwrap_or(false)
+        };
+
+        self.world.solids.retain(|s| !owned(&s.editor));
+        self.entities.retain(|e| !owned(&e.editor));
+
+        self.world.solids.extend(
+            generated
+                .world
+                .solids
+                .into_iter()
+                .filter(|s| owned(&s.editor)),
+        );
+        self.entities
+            .extend(generated.entities.into_iter().filter(|e| owned(&e.editor)));
+
+        for visgroup in generated.visgroups {
+            if visgroups.contains(&visgroup.id) && !self.has_visgroup(visgroup.id) {
+                self.visgroups.push(visgroup);
+            }
+        }
+    }
+
+    /// Every brush side in the map, world and brush entities alike.
+    pub fn sides(&self) -> impl Iterator<Item = &Side> {
+        self.world
+            .solids
+            .iter()
+            .chain(self.entities.iter().flat_map(|e| e.solids.iter()))
+            .flat_map(|s| s.sides.iter())
+    }
+
+    pub fn sides_mut(&mut self) -> impl Iterator<Item = &mut Side> {
+        self.world
+            .solids
+            .iter_mut()
+            .chain(self.entities.iter_mut().flat_map(|e| e.solids.iter_mut()))
+            .flat_map(|s| s.sides.iter_mut())
+    }
+
+    /// Sides of the solids hidden in the editor, which are still raw blocks in `extra`.
+    pub fn hidden_sides_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.world
+            .extra
+            .iter_mut()
+            .chain(self.entities.iter_mut().flat_map(|e| e.extra.iter_mut()))
+            .filter(|n| n.name == "hidden")
+            .flat_map(|n| n.children.iter_mut().filter(|c| c.name == "solid"))
+            .flat_map(|n| n.children.iter_mut().filter(|c| c.name == "side"))
+    }
+
+    fn has_visgroup(&self, id: u32) -> bool {
+        fn walk(visgroups: &[VisGroup], id: u32) -> bool {
+            visgroups
+                .iter()
+                .any(|v| v.id == id || walk(&v.children, id))
+        }
+        walk(&self.visgroups, id)
+    }
+}
+
+/// Writes KeyValues with indentation and hands out ids as it goes, so every solid, side, entity
+/// and the world get a unique one without the callers tracking anything.
+struct Writer<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    depth: usize,
+    next_id: usize,
+    /// Group ids, which editors point at through `groupid`, so they keep theirs.
+    reserved: HashSet<usize>,
+}
+
+// VMF has no escape sequences, so anything that would end the string or the line has to go
+pub fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '"' => '\'',
+            '\r' | '\n' => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+fn bool_str(b: bool) -> &'static str {
+    if b {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+fn vec3(v: Vec3) -> String {
+    format!("{} {} {}", v.0, v.1, v.2)
+}
+
+fn color(c: Color) -> String {
+    format!("{} {} {}", c.0, c.1, c.2)
+}
+
+impl Writer<'_, '_> {
+    fn indent(&mut self) -> fmt::Result {
+        for _ in 0..self.depth {
+            self.f.write_str("  ")?;
+        }
+        Ok(())
+    }
+
+    fn kv(&mut self, key: &str, value: impl AsRef<str>) -> fmt::Result {
+        self.indent()?;
+        writeln!(self.f, "\"{}\" \"{}\"", escape(key), escape(value.as_ref()))
+    }
+
+    fn id(&mut self) -> fmt::Result {
+        while self.reserved.contains(&self.next_id) {
+            self.next_id += 1;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.kv("id", id.to_string())
+    }
+
+    fn block(&mut self, name: &str, body: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        self.indent()?;
+        writeln!(self.f, "{}", name)?;
+        self.indent()?;
+        writeln!(self.f, "{{")?;
+        self.depth += 1;
+        body(self)?;
+        self.depth -= 1;
+        self.indent()?;
+        writeln!(self.f, "}}")
+    }
+
+    fn visgroup(&mut self, visgroup: &VisGroup) -> fmt::Result {
+        self.block("visgroup", |w| {
+            w.kv("name", &visgroup.name)?;
+            w.kv("visgroupid", visgroup.id.to_string())?;
+            w.kv("color", color(visgroup.color))?;
+            w.kv("visible", bool_str(visgroup.visible))?;
+            for child in &visgroup.children {
+                w.visgroup(child)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn editor(&mut self, editor: &Editor) -> fmt::Result {
+        self.block("editor", |w| {
+            w.kv("color", color(editor.color))?;
+            if let Some(visgroup_id) = editor.visgroup_id {
+                w.kv("visgroupid", visgroup_id.to_string())?;
+            }
+            w.kv("visgroupshown", bool_str(editor.visgroup_shown))?;
+            w.properties(&editor.extra)
+        })
+    }
+
+    fn solid(&mut self, solid: &Solid) -> fmt::Result {
+        self.block("solid", |w| {
+            w.id()?;
+            for side in &solid.sides {
+                w.block("side", |w| {
+                    w.id()?;
+                    let [a, b, c] = side.plane;
+                    w.kv(
+                        "plane",
+                        format!("({}) ({}) ({})", vec3(a), vec3(b), vec3(c)),
+                    )?;
+                    w.kv("material", &side.material)?;
+                    for (key, axis) in [("uaxis", side.uaxis), ("vaxis", side.vaxis)] {
+                        w.kv(
+                            key,
+                            format!("[{} {}] {}", vec3(axis.axis), axis.offset, axis.scale),
+                        )?;
+                    }
+                    w.kv("rotation", side.rotation.to_string())?;
+                    w.kv("lightmapscale", side.lightmap_scale.to_string())?;
+                    w.kv("smoothing_groups", side.smoothing_groups.to_string())?;
+                    w.nodes(&side.extra)
+                })?;
+            }
+            w.editor(&solid.editor)?;
+            w.nodes(&solid.extra)
+        })
+    }
+
+    fn properties(&mut self, properties: &[(String, String)]) -> fmt::Result {
+        for (key, value) in properties {
+            self.kv(key, value)?;
+        }
+        Ok(())
+    }
+
+    // Raw blocks keep everything but their ids, which get renumbered like the rest. Groups are
+    // the exception, editors refer to them by id
+    fn node(&mut self, node: &Node) -> fmt::Result {
+        self.block(&node.name, |w| {
+            for (key, value) in &node.properties {
+                if key == "id" && node.name != "group" {
+                    w.id()?;
+                } else {
+                    w.kv(key, value)?;
+                }
+            }
+            for child in &node.children {
+                w.node(child)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> fmt::Result {
+        for node in nodes {
+            self.node(node)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Vmf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn group_ids(nodes: &[Node], ids: &mut HashSet<usize>) {
+            for node in nodes {
+                if node.name == "group" {
+                    ids.extend(
+                        node.get("id")
+                            .and_then(|id| id.trim().parse::<usize>().ok()),
+                    );
+                }
+                group_ids(&node.children, ids);
+            }
+        }
+        let mut reserved = HashSet::new();
+        group_ids(&self.world.extra, &mut reserved);
+        group_ids(&self.extra, &mut reserved);
+        for entity in &self.entities {
+            group_ids(&entity.extra, &mut reserved);
+        }
+
+        let mut w = Writer {
+            f,
+            depth: 0,
+            next_id: 1,
+            reserved,
+        };
+
+        w.block("versioninfo", |w| {
+            let info = &self.version_info;
+            w.kv("editorversion", info.editor_version.to_string())?;
+            w.kv("editorbuild", info.editor_build.to_string())?;
+            w.kv("mapversion", info.map_version.to_string())?;
+            w.kv("formatversion", info.format_version.to_string())?;
+            w.kv("prefab", bool_str(info.prefab))
+        })?;
+
+        w.block("visgroups", |w| {
+            for visgroup in &self.visgroups {
+                w.visgroup(visgroup)?;
+            }
+            Ok(())
+        })?;
+
+        w.block("viewsettings", |w| {
+            let settings = &self.view_settings;
+            w.kv("bSnapToGrid", bool_str(settings.snap_to_grid))?;
+            w.kv("bShowGrid", bool_str(settings.show_grid))?;
+            w.kv("bShow3DGrid", bool_str(settings.show_3d_grid))?;
+            w.kv("nGridSpacing", settings.grid_spacing.to_string())
+        })?;
+
+        w.block("world", |w| {
+            w.id()?;
+            w.properties(&self.world.properties)?;
+            for solid in &self.world.solids {
+                w.solid(solid)?;
+            }
+            w.nodes(&self.world.extra)
+        })?;
+
+        for entity in &self.entities {
+            w.block("entity", |w| {
+                w.id()?;
+                w.kv("classname", &entity.classname)?;
+                w.properties(&entity.properties)?;
+                for solid in &entity.solids {
+                    w.solid(solid)?;
+                }
+                w.editor(&entity.editor)?;
+                w.nodes(&entity.extra)
+            })?;
+        }
+
+        w.block("cameras", |w| {
+            w.kv("activecamera", self.cameras.active.to_string())?;
+            for camera in &self.cameras.cameras {
+                w.block("camera", |w| {
+                    w.kv("position", format!("[{}]", vec3(camera.position)))?;
+                    w.kv("look", format!("[{}]", vec3(camera.look)))
+                })?;
+            }
+            Ok(())
+        })?;
+
+        w.block("cordon", |w| {
+            w.kv("mins", format!("({})", vec3(self.cordon.mins)))?;
+            w.kv("maxs", format!("({})", vec3(self.cordon.maxs)))?;
+            w.kv("active", bool_str(self.cordon.active))
+        })?;
+
+        w.nodes(&self.extra)
+    }
+}
+
+// KeyValues tokens: quoted or bare strings, braces, and `//` comments to skip
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("//") {
+            rest = rest.split_once('\n').map(|(_, r)| r).unwrap_or("");
+            continue;
+        }
+
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        match c {
+            '{' => {
+                tokens.push(Token::Open);
+                rest = &rest[1..];
+            }
+            '}' => {
+                tokens.push(Token::Close);
+                rest = &rest[1..];
+            }
+            '"' => {
+                let end = rest[1..].find('"').map(|i| i + 1).unwrap_or(rest.len());
+                tokens.push(Token::String(&rest[1..end]));
+                rest = rest.get(end + 1..).unwrap_or("");
+            }
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '{' || c == '}' || c == '"')
+                    .unwrap_or(rest.len());
+                tokens.push(Token::String(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+    }
+
+    tokens
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token<'a> {
+    String(&'a str),
+    Open,
+    Close,
+}
+
+// Reads `key "value"` and `name { ... }` pairs until a closing brace or the end
+fn parse_body<'a>(
+    tokens: &mut impl Iterator<Item = Token<'a>>,
+    node: &mut Node,
+) -> anyhow::Result<()> {
+    while let Some(token) = tokens.next() {
+        let key = match token {
+            Token::String(key) => key,
+            Token::Close => return Ok(()),
+            Token::Open => anyhow::bail!("unexpected {{ in {}", node.name),
+        };
+
+        match tokens.next() {
+            Some(Token::String(value)) => {
+                node.properties.push((key.to_string(), value.to_string()))
+            }
+            Some(Token::Open) => {
+                let mut child = Node {
+                    name: key.to_string(),
+                    properties: Vec::new(),
+                    children: Vec::new(),
+                };
+                parse_body(tokens, &mut child)?;
+                node.children.push(child);
+            }
+            _ => anyhow::bail!("{} in {} has no value", key, node.name),
+        }
+    }
+    Ok(())
+}
+
+fn numbers(s: &str) -> anyhow::Result<Vec<f64>> {
+    s.split(|c: char| c.is_whitespace() || "()[]".contains(c))
+        .filter(|n| !n.is_empty())
+        .map(|n| {
+            n.parse::<f64>()
+                .with_context(|| format!("bad number in {:?}", s))
+        })
+        .collect()
+}
+
+pub fn parse_vec3(s: &str) -> anyhow::Result<Vec3> {
+    match numbers(s)?[..] {
+        [x, y, z] => Ok((x, y, z)),
+        _ => anyhow::bail!("expected three numbers in {:?}", s),
+    }
+}
+
+fn parse_color(s: &str) -> Color {
+    let mut parts = s.split_whitespace().map(|n| n.parse().unwrap_or(255));
+    (
+        parts.next().unwrap_or(255),
+        parts.next().unwrap_or(255),
+        parts.next().unwrap_or(255),
+    )
+}
+
+fn parse_num<T: std::str::FromStr + Default>(node: &Node, key: &str) -> T {
+    node.get(key)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+fn parse_bool(node: &Node, key: &str) -> bool {
+    node.get(key).map(|v| v.trim() != "0").unwrap_or(false)
+}
+
+fn parse_visgroup(node: &Node) -> VisGroup {
+    VisGroup {
+        name: node.get("name").unwrap_or_default().to_string(),
+        id: parse_num(node, "visgroupid"),
+        color: parse_color(node.get("color").unwrap_or("255 255 255")),
+        visible: node.get("visible").is_none() || parse_bool(node, "visible"),
+        children: node
+            .children
+            .iter()
+            .filter(|c| c.name == "visgroup")
+            .map(parse_visgroup)
+            .collect(),
+    }
+}
+
+fn parse_editor(node: &Node) -> Editor {
+    let mut editor = Editor {
+        color: node.get("color").map(parse_color).unwrap_or((0, 255, 0)),
+        visgroup_id: None,
+        visgroup_shown: node.get("visgroupshown").is_none() || parse_bool(node, "visgroupshown"),
+        extra: Vec::new(),
+    };
+    // Only the first visgroup gets swapped out on merge, any others ride along untouched
+    for (key, value) in &node.properties {
+        match key.to_ascii_lowercase().as_str() {
+            "color" | "visgroupshown" => {}
+            "visgroupid" if editor.visgroup_id.is_none() => {
+                editor.visgroup_id = value.trim().parse().ok()
+            }
+            _ => editor.extra.push((key.clone(), value.clone())),
+        }
+    }
+    editor
+}
+
+fn parse_axis(s: &str) -> anyhow::Result<TextureAxis> {
+    match numbers(s)?[..] {
+        [x, y, z, offset, scale] => Ok(TextureAxis {
+            axis: (x, y, z),
+            offset,
+            scale,
+        }),
+        _ => anyhow::bail!("malformed texture axis {:?}", s),
+    }
+}
+
+fn parse_side(node: &Node) -> anyhow::Result<Side> {
+    let plane = numbers(node.get("plane").context("side has no plane")?)?;
+    let [a, b, c, d, e, f, g, h, i] = plane[..] else {
+        anyhow::bail!("side plane needs three points");
+    };
+
+    Ok(Side {
+        plane: [(a, b, c), (d, e, f), (g, h, i)],
+        material: node.get("material").unwrap_or_default().to_string(),
+        uaxis: parse_axis(node.get("uaxis").context("side has no uaxis")?)?,
+        vaxis: parse_axis(node.get("vaxis").context("side has no vaxis")?)?,
+        rotation: parse_num(node, "rotation"),
+        lightmap_scale: parse_num(node, "lightmapscale"),
+        smoothing_groups: parse_num(node, "smoothing_groups"),
+        extra: node.children.clone(),
+    })
+}
+
+pub fn parse_solid(node: &Node) -> anyhow::Result<Solid> {
+    let mut solid = Solid {
+        sides: Vec::new(),
+        editor: Editor::default(),
+        extra: Vec::new(),
+    };
+    for child in &node.children {
+        match child.name.as_str() {
+            "side" => solid.sides.push(parse_side(child)?),
+            "editor" => solid.editor = parse_editor(child),
+            _ => solid.extra.push(child.clone()),
+        }
+    }
+    Ok(solid)
+}
+
+// Worlds and entities have the same shape, so both get read as an entity first
+fn parse_entity(node: &Node) -> anyhow::Result<Entity> {
+    let mut entity = Entity::new("");
+    for (key, value) in &node.properties {
+        match key.as_str() {
+            "id" => {}
+            "classname" => entity.classname = value.clone(),
+            _ => entity.properties.push((key.clone(), value.clone())),
+        }
+    }
+
+    for child in &node.children {
+        match child.name.as_str() {
+            "solid" => entity.solids.push(parse_solid(child)?),
+            "editor" => entity.editor = parse_editor(child),
+            _ => entity.extra.push(child.clone()),
+        }
+    }
+
+    Ok(entity)
+}
+
+impl std::str::FromStr for Vmf {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut root = Node {
+            name: "root".to_string(),
+            properties: Vec::new(),
+            children: Vec::new(),
+        };
+        parse_body(&mut tokenize(s).into_iter(), &mut root)?;
+
+        let mut vmf = Vmf {
+            version_info: VersionInfo::default(),
+            visgroups: Vec::new(),
+            view_settings: ViewSettings::default(),
+            world: World {
+                properties: Vec::new(),
+                solids: Vec::new(),
+                extra: Vec::new(),
+            },
+            entities: Vec::new(),
+            cameras: Cameras::default(),
+            cordon: Cordon::default(),
+            extra: Vec::new(),
+        };
+
+        for node in root.children {
+            match node.name.as_str() {
+                "versioninfo" => {
+                    vmf.version_info = VersionInfo {
+                        editor_version: parse_num(&node, "editorversion"),
+                        editor_build: parse_num(&node, "editorbuild"),
+                        map_version: parse_num(&node, "mapversion"),
+                        format_version: parse_num(&node, "formatversion"),
+                        prefab: parse_bool(&node, "prefab"),
+                    }
+                }
+                "visgroups" => {
+                    vmf.visgroups = node
+                        .children
+                        .iter()
+                        .filter(|c| c.name == "visgroup")
+                        .map(parse_visgroup)
+                        .collect();
+                }
+                "viewsettings" => {
+                    vmf.view_settings = ViewSettings {
+                        snap_to_grid: parse_bool(&node, "bSnapToGrid"),
+                        show_grid: parse_bool(&node, "bShowGrid"),
+                        show_3d_grid: parse_bool(&node, "bShow3DGrid"),
+                        grid_spacing: parse_num(&node, "nGridSpacing"),
+                    }
+                }
+                "world" => {
+                    let world = parse_entity(&node)?;
+                    vmf.world = World {
+                        // Worldspawn keeps its classname in with the rest
+                        properties: node
+                            .properties
+                            .iter()
+                            .filter(|(k, _)| k != "id")
+                            .cloned()
+                            .collect(),
+                        solids: world.solids,
+                        extra: world.extra,
+                    };
+                }
+                "entity" => vmf.entities.push(parse_entity(&node)?),
+                "cameras" => {
+                    vmf.cameras = Cameras {
+                        active: parse_num(&node, "activecamera"),
+                        cameras: node
+                            .children
+                            .iter()
+                            .filter(|c| c.name == "camera")
+                            .map(|c| {
+                                Ok(Camera {
+                                    position: parse_vec3(c.get("position").unwrap_or("0 0 0"))?,
+                                    look: parse_vec3(c.get("look").unwrap_or("0 0 0"))?,
+                                })
+                            })
+                            .collect::<anyhow::Result<_>>()?,
+                    }
+                }
+                "cordon" => {
+                    vmf.cordon = Cordon {
+                        mins: parse_vec3(node.get("mins").unwrap_or("0 0 0"))?,
+                        maxs: parse_vec3(node.get("maxs").unwrap_or("0 0 0"))?,
+                        active: parse_bool(&node, "active"),
+                    }
+                }
+                _ => vmf.extra.push(node),
+            }
+        }
+
+        Ok(vmf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GROUPED: &str = r#"
+world
+{
+    "id" "1"
+    "classname" "worldspawn"
+    solid
+    {
+        "id" "2"
+        side
+        {
+            "id" "3"
+            "plane" "(0 0 64) (0 64 64) (64 64 64)"
+            "material" "stone"
+            "uaxis" "[1 0 0 0] 0.25"
+            "vaxis" "[0 -1 0 0] 0.25"
+        }
+        editor
+        {
+            "color" "0 255 0"
+            "groupid" "2"
+            "visgroupshown" "1"
+        }
+    }
+    group
+    {
+        "id" "2"
+        editor
+        {
+            "color" "0 100 200"
+            "visgroupshown" "1"
+        }
+    }
+}
+"#;
+
+    fn group_ids(vmf: &Vmf) -> Vec<String> {
+        vmf.world
+            .extra
+            .iter()
+            .filter(|n| n.name == "group")
+            .filter_map(|n| n.get("id").map(|id| id.to_string()))
+            .collect()
+    }
+
+    fn groupids(vmf: &Vmf) -> Vec<String> {
+        vmf.world
+            .solids
+            .iter()
+            .flat_map(|s| &s.editor.extra)
+            .filter(|(k, _)| k == "groupid")
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    #[test]
+    fn groups_survive_a_round_trip() {
+        let vmf: Vmf = GROUPED.parse().unwrap();
+        let written: Vmf = vmf.to_string().parse().unwrap();
+        assert_eq!(group_ids(&written), ["2"]);
+        assert_eq!(groupids(&written), ["2"]);
+
+        // And nothing else got the group's id
+        let text = written.to_string();
+        assert_eq!(text.matches("\"id\" \"2\"").count(), 1);
+    }
+}
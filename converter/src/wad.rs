@@ -0,0 +1,286 @@
+use crate::{util, vmf};
+use common::palette;
+use image::RgbaImage;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const TYP_MIPTEX: u8 = 0x43;
+// hlcsg and the engine both choke on longer names
+const MAX_NAME_LEN: usize = 15;
+
+/// Textures that come from the compilers/engine, not from our WAD.
+fn is_tool_texture(name: &str) -> bool {
+    matches!(
+        name.to_uppercase().as_str(),
+        "SKY" | "CLIP" | "NULL" | "SKIP" | "HINT" | "ORIGIN" | "AAATRIGGER" | "BEVEL" | "MISSING"
+    )
+}
+
+/// Splits off the prefixes the compilers and engine give meaning to: `{` masked, `!` water,
+/// `+0`..`+9`/`+a`..`+j` animated or toggled, `-0`..`-9` random tiling.
+fn split_prefix(name: &str) -> (&str, &str) {
+    let bytes = name.as_bytes();
+    let len = match bytes {
+        [b'{' | b'!', ..] => 1,
+        [b'+', c, _, ..]
+            if c.is_ascii_digit() || (b'a'..=b'j').contains(&c.to_ascii_lowercase()) =>
+        {
+            2
+        }
+        [b'-', c, _, ..] if c.is_ascii_digit() => 2,
+        _ => 0,
+    };
+    name.split_at(len)
+}
+
+// FNV-1a, so names come out the same on every run and every build
+fn name_hash(name: &str) -> u32 {
+    name.bytes().fold(0x811c9dc5, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Renames every texture in the map that's too long (or has characters hlcsg won't take) to a
+/// unique short one, and returns short name -> original name for everything the WAD will hold.
+/// Names are shortened without their prefix, so animation frames and masked variants of the
+/// same texture stay together. The engine ignores case, so names only differing in case become
+/// one texture.
+pub fn allocate_names(vmf: &mut vmf::Vmf) -> BTreeMap<String, String> {
+    let mut names = vmf.sides().map(|s| s.material.clone()).collect::<Vec<_>>();
+    names.extend(
+        vmf.hidden_sides_mut()
+            .filter_map(|n| n.get("material").map(|m| m.to_string())),
+    );
+    names.retain(|m| !is_tool_texture(m));
+    names.sort_by_cached_key(|n| (n.to_ascii_lowercase(), n.clone()));
+    names.dedup();
+    let mut unique = names.clone();
+    unique.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+
+    // Each body has to fit next to the longest prefix it's used with
+    let mut bodies: BTreeMap<String, (&str, usize)> = BTreeMap::new();
+    for name in &unique {
+        let (prefix, body) = split_prefix(name);
+        let (_, longest) = bodies.entry(body.to_ascii_lowercase()).or_insert((body, 0));
+        *longest = (*longest).max(prefix.len());
+    }
+
+    let valid = |body: &str| body.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    let mut used = bodies
+        .iter()
+        .filter(|(key, (_, prefix))| key.len() + *prefix <= MAX_NAME_LEN && valid(key))
+        .map(|(key, _)| key.clone())
+        .collect::<HashSet<_>>();
+
+    let mut short_bodies = BTreeMap::new();
+    for (key, (body, prefix)) in &bodies {
+        if body.len() + prefix <= MAX_NAME_LEN && valid(body) {
+            short_bodies.insert(key.as_str(), body.to_string());
+            continue;
+        }
+
+        // Readable start, then a hash of the whole thing to keep it unique
+        let keep = MAX_NAME_LEN - prefix - 5;
+        let start = body
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .take(keep)
+            .collect::<String>();
+        let mut hash = name_hash(body);
+        let short = loop {
+            let short = format!("{}_{:04x}", start, hash & 0xffff);
+            if used.insert(short.to_lowercase()) {
+                break short;
+            }
+            hash = hash.wrapping_add(1);
+        };
+        short_bodies.insert(key.as_str(), short);
+    }
+
+    let short_name = |name: &str| {
+        let (prefix, body) = split_prefix(name);
+        format!(
+            "{}{}",
+            prefix,
+            short_bodies[body.to_ascii_lowercase().as_str()]
+        )
+    };
+    // Every spelling goes to whichever one got picked for the WAD
+    let picked = unique
+        .iter()
+        .map(|name| (name.to_ascii_lowercase(), short_name(name)))
+        .collect::<HashMap<_, _>>();
+    let renames = names
+        .iter()
+        .map(|name| (name.clone(), picked[&name.to_ascii_lowercase()].clone()))
+        .collect::<BTreeMap<_, _>>();
+    for side in vmf.sides_mut() {
+        if let Some(short) = renames.get(&side.material) {
+            side.material = short.clone();
+        }
+    }
+    for side in vmf.hidden_sides_mut() {
+        if let Some(material) = side.get_mut("material") {
+            if let Some(short) = renames.get(material.as_str()) {
+                *material = short.clone();
+            }
+        }
+    }
+
+    unique
+        .iter()
+        .map(|name| (short_name(name), name.clone()))
+        .collect()
+}
+
+/// Lists short name -> the file it was made from, one per line, for finding where a WAD texture
+/// came from.
+pub fn manifest(names: &BTreeMap<String, String>, textures_dir: &str) -> String {
+    names
+        .iter()
+        .map(|(short, long)| {
+            format!(
+                "{} {}\n",
+                short,
+                util::png_path(textures_dir, long).display()
+            )
+        })
+        .collect()
+}
+
+/// Downscales by `factor`, giving `None` for pixels that end up mostly transparent. Without
+/// `masked` the alpha channel is ignored.
+fn downsample(image: &RgbaImage, factor: u32, masked: bool) -> Vec<Option<palette::Rgb>> {
+    let (width, height) = (image.width() / factor, image.height() / factor);
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 3];
+            let mut opaque = 0;
+            for dy in 0..factor {
+                for dx in 0..factor {
+                    let p = image.get_pixel(x * factor + dx, y * factor + dy);
+                    if masked && p[3] < 128 {
+                        continue;
+                    }
+                    for c in 0..3 {
+                        sum[c] += p[c] as u32;
+                    }
+                    opaque += 1;
+                }
+            }
+
+            if opaque * 2 < factor * factor || opaque == 0 {
+                pixels.push(None);
+            } else {
+                pixels.push(Some(sum.map(|s| (s / opaque) as u8)));
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Builds one miptex lump: header, four mip levels, then the palette.
+fn build_miptex(name: &str, image: &RgbaImage) -> Vec<u8> {
+    // Dimensions have to be multiples of 16
+    let width = (image.width().div_ceil(16) * 16).max(16);
+    let height = (image.height().div_ceil(16) * 16).max(16);
+    let image = if (width, height) != image.dimensions() {
+        image::imageops::resize(image, width, height, image::imageops::FilterType::Nearest)
+    } else {
+        image.clone()
+    };
+
+    // `{` textures are masked: index 255 is see-through and has to be pure blue
+    let masked = name.starts_with('{');
+    let mips = [1, 2, 4, 8].map(|factor| downsample(&image, factor, masked));
+    let mut colors = palette::median_cut(
+        mips[0].iter().flatten().copied(),
+        if masked { 255 } else { 256 },
+    );
+    colors.resize(256, [0, 0, 0]);
+    if masked {
+        colors[255] = [0, 0, 255];
+    }
+    let usable = if masked { &colors[..255] } else { &colors[..] };
+
+    let mut lump = Vec::new();
+    let mut name_bytes = [0u8; 16];
+    name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+    lump.extend_from_slice(&name_bytes);
+    lump.extend_from_slice(&width.to_le_bytes());
+    lump.extend_from_slice(&height.to_le_bytes());
+
+    let mut offset = 40u32;
+    for mip in &mips {
+        lump.extend_from_slice(&offset.to_le_bytes());
+        offset += mip.len() as u32;
+    }
+    for (level, mip) in mips.iter().enumerate() {
+        lump.extend(palette::remap(
+            mip,
+            (width >> level) as usize,
+            usable,
+            false,
+        ));
+    }
+
+    lump.extend_from_slice(&256u16.to_le_bytes());
+    for color in &colors {
+        lump.extend_from_slice(color);
+    }
+    // Pad the lump to a multiple of four
+    lump.resize(lump.len().next_multiple_of(4), 0);
+    lump
+}
+
+/// Writes a WAD3 with every texture in `names` (from [`allocate_names`]), loading each from the
+/// original name's PNG in `textures_dir`. Returns the textures it couldn't include.
+pub fn write(
+    path: &str,
+    names: &BTreeMap<String, String>,
+    textures_dir: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mut lumps = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, original) in names {
+        if name.len() > MAX_NAME_LEN {
+            skipped.push(original.to_string());
+            continue;
+        }
+        let Some(image) = util::load_png(textures_dir, original)? else {
+            skipped.push(original.to_string());
+            continue;
+        };
+        lumps.push((name, build_miptex(name, &image)));
+    }
+
+    let mut wad = Vec::new();
+    wad.extend_from_slice(b"WAD3");
+    wad.extend_from_slice(&(lumps.len() as u32).to_le_bytes());
+    wad.extend_from_slice(&0u32.to_le_bytes()); // directory offset, patched below
+
+    let mut directory = Vec::new();
+    for (name, lump) in &lumps {
+        let offset = wad.len() as u32;
+        wad.extend_from_slice(lump);
+
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(&(lump.len() as u32).to_le_bytes()); // disk size
+        directory.extend_from_slice(&(lump.len() as u32).to_le_bytes()); // size
+        directory.push(TYP_MIPTEX);
+        directory.push(0); // not compressed
+        directory.extend_from_slice(&[0, 0]);
+        let mut name_bytes = [0u8; 16];
+        name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+        directory.extend_from_slice(&name_bytes);
+    }
+
+    let directory_offset = wad.len() as u32;
+    wad[8..12].copy_from_slice(&directory_offset.to_le_bytes());
+    wad.extend_from_slice(&directory);
+
+    std::fs::write(path, wad)?;
+    Ok(skipped)
+}
@@ -1,3 +1,4 @@
+use common::{mdl, palette, transform};
 use serde::Deserialize;
 use std::collections::HashMap;
 
@@ -9,24 +10,22 @@ struct Config {
     blocks: Vec<String>,
     single: bool,
     output_name: String,
+    #[serde(default)]
+    dither: bool,
+    /// Has to match whatever the converter was given with `--transform`.
+    #[serde(default)]
+    transform: transform::Transform,
 }
 
 #[derive(Debug, Deserialize)]
 struct Block {
     pos: (i32, i32, i32),
     id: String,
+    // Part of the export format, models don't care about block states
+    #[allow(dead_code)]
     props: Option<String>,
 }
 
-const MC_TO_HAMMER: f64 = 48.;
-
-fn fix_coords(pos: (f64, f64, f64)) -> (f64, f64, f64) {
-    // Offset to fit in the map and convert to Hammer units (Z up)
-    let pos = (pos.0, pos.2, pos.1);
-    let offset = (0., -(4096. - 256.), -512.);
-    (pos.0 + offset.0, pos.1 + offset.1, pos.2 + offset.2)
-}
-
 fn main() -> anyhow::Result<()> {
     let config: Config = serde_json::from_str(&std::fs::read_to_string("config.json")?)?;
     let schema: Vec<Block> = serde_json::from_str(include_str!("jaybirthday.json"))?;
@@ -41,6 +40,16 @@ fn main() -> anyhow::Result<()> {
         schema.iter().map(|b| b.pos.1).max().unwrap(),
         schema.iter().map(|b| b.pos.2).max().unwrap(),
     );
+    let transform = config.transform.with_size((
+        (max.0 - min.0 + 1) as usize,
+        (max.1 - min.1 + 1) as usize,
+        (max.2 - min.2 + 1) as usize,
+    ))?;
+    let scale = transform.scale;
+    let orient = |v: (f32, f32, f32)| {
+        let v = transform.direction((v.0 as f64, v.2 as f64, v.1 as f64));
+        (v.0 as f32, v.1 as f32, v.2 as f32)
+    };
 
     let mut all_positions = HashMap::new();
 
@@ -56,20 +65,12 @@ fn main() -> anyhow::Result<()> {
 
             // Offset the negative so it starts at zero
             let pos = (
-                (block.pos.0 - min.0) as usize,
-                (block.pos.1 - min.1) as usize,
-                (block.pos.2 - min.2) as usize,
-            );
-            // Flip it on the Z axis because Hammer moment (janky hack to do it here)
-            let pos = (pos.0, pos.1, (max.2 - min.2) as usize - pos.2);
-
-            // Then scale up
-            let pos = (
-                pos.0 as f64 * MC_TO_HAMMER,
-                pos.1 as f64 * MC_TO_HAMMER,
-                pos.2 as f64 * MC_TO_HAMMER,
+                (block.pos.0 - min.0) as f64,
+                (block.pos.1 - min.1) as f64,
+                (block.pos.2 - min.2) as f64,
             );
-            let pos = fix_coords(pos);
+            // The block's corner closest to Hammer's origin
+            let (pos, _) = transform.bounds(pos, (pos.0 + 1., pos.1 + 1., pos.2 + 1.));
 
             if pos.0 < config.min.0
                 || pos.1 < config.min.1
@@ -108,6 +109,13 @@ $body "studio" "{}"
 "#,
         config.output_name, config.output_name
     );
+    let mut mdl = mdl::Model {
+        name: config.output_name.clone(),
+        // Matches the $origin rotation in the QC
+        rotation: 0.,
+        textures: Vec::new(),
+        meshes: Vec::new(),
+    };
 
     for (id, positions) in all_positions {
         let (models, materials) = tobj::load_obj(
@@ -127,23 +135,42 @@ $body "studio" "{}"
             // Extract the diffuse texture to a bmp
             let diffuse_name = material.diffuse_texture.clone().unwrap();
             let bmp_name = diffuse_name.replace(".png", ".bmp");
-            if !material_files.iter().any(|m| *m == bmp_name) {
+            if !material_files.contains(&bmp_name) {
                 material_files.push(bmp_name.clone());
             }
 
-            let image_converter = "./ImageConverter.exe";
-            std::process::Command::new(image_converter)
-                .arg(format!("./models/{}", diffuse_name))
-                .stdout(std::process::Stdio::piped())
-                .spawn()?
-                .wait_with_output()?;
+            let texture = match mdl.textures.iter().position(|t| t.name == bmp_name) {
+                Some(texture) => texture,
+                None => {
+                    let image = image::open(format!("./models/{}", diffuse_name))?.to_rgba8();
+                    let image = palette::quantize(&image, true, config.dither);
+                    image.write_bmp(format!("./models_out/{}", bmp_name))?;
+                    mdl.textures.push(mdl::Texture {
+                        name: bmp_name.clone(),
+                        image,
+                        masked: true,
+                    });
+                    mdl.textures.len() - 1
+                }
+            };
+            // Every block using this texture goes in the same mesh
+            let mesh_index = match mdl.meshes.iter().position(|m| m.texture == texture) {
+                Some(mesh_index) => mesh_index,
+                None => {
+                    mdl.meshes.push(mdl::Mesh {
+                        texture,
+                        triangles: Vec::new(),
+                    });
+                    mdl.meshes.len() - 1
+                }
+            };
 
             for position in &positions {
                 // Center
                 let mut position = (
-                    position.0 + (MC_TO_HAMMER / 2.),
-                    position.1 + (MC_TO_HAMMER / 2.),
-                    position.2 + (MC_TO_HAMMER / 2.),
+                    position.0 + (scale / 2.),
+                    position.1 + (scale / 2.),
+                    position.2 + (scale / 2.),
                 );
 
                 // Offset to the origin
@@ -152,31 +179,39 @@ $body "studio" "{}"
                 position.2 -= config.origin.2;
 
                 for idx in 0..mesh.indices.len() / 3 {
-                    let mut face = format!("{}\n", diffuse_name.replace(".png", ".bmp"));
-                    for vtx in 0..3 {
+                    let mut face = format!("{}\n", bmp_name);
+                    let mut triangle = Vec::new();
+                    // A mirrored transform turns the triangles inside out, so wind them backwards
+                    let order = if transform.mirrored() {
+                        [0, 2, 1]
+                    } else {
+                        [0, 1, 2]
+                    };
+                    for vtx in order {
                         let mut pos = (
                             mesh.positions[(mesh.indices[(idx * 3) + vtx] * 3) as usize],
                             mesh.positions[(mesh.indices[(idx * 3) + vtx] * 3 + 1) as usize],
                             mesh.positions[(mesh.indices[(idx * 3) + vtx] * 3 + 2) as usize],
                         );
                         // HACK
-                        pos.0 *= MC_TO_HAMMER as f32;
-                        pos.1 *= MC_TO_HAMMER as f32;
-                        pos.2 *= MC_TO_HAMMER as f32;
+                        pos.0 *= scale as f32;
+                        pos.1 *= scale as f32;
+                        pos.2 *= scale as f32;
 
-                        // Swap to Z-up
-                        pos = (pos.0, pos.2, pos.1);
+                        // Models are Y-up with Z going north, turn them the same way the
+                        // transform turns the blocks around them
+                        pos = orient(pos);
 
                         // Offset to this position
                         pos.0 += position.0 as f32;
                         pos.1 += position.1 as f32;
                         pos.2 += position.2 as f32;
 
-                        let normal = (
+                        let normal = orient((
                             mesh.normals[(mesh.normal_indices[(idx * 3) + vtx] * 3) as usize],
                             mesh.normals[(mesh.normal_indices[(idx * 3) + vtx] * 3 + 1) as usize],
                             mesh.normals[(mesh.normal_indices[(idx * 3) + vtx] * 3 + 2) as usize],
-                        );
+                        ));
                         let uv = (
                             mesh.texcoords[(mesh.texcoord_indices[(idx * 3) + vtx] * 2) as usize],
                             mesh.texcoords
@@ -187,8 +222,16 @@ $body "studio" "{}"
                             "0 {} {} {} {} {} {} {} {}\n",
                             pos.0, pos.1, pos.2, normal.0, normal.1, normal.2, uv.0, uv.1
                         );
+                        triangle.push(mdl::Vertex {
+                            pos: [pos.0, pos.1, pos.2],
+                            normal: [normal.0, normal.1, normal.2],
+                            uv: [uv.0, uv.1],
+                        });
                     }
                     smd += &face;
+                    mdl.meshes[mesh_index]
+                        .triangles
+                        .push([triangle[0], triangle[1], triangle[2]]);
                 }
             }
         }
@@ -209,12 +252,9 @@ $body "studio" "{}"
     );
     std::fs::write(format!("./models_out/{}.smd", config.output_name), smd)?;
     std::fs::write(format!("./models_out/{}.qc", config.output_name), qc)?;
-
-    std::process::Command::new("./studiomdl.exe")
-        .arg(format!("./{}.qc", config.output_name))
-        .current_dir("./models_out")
-        .stdout(std::process::Stdio::piped())
-        .spawn()?
-        .wait_with_output()?;
+    std::fs::write(
+        format!("./models_out/{}.mdl", config.output_name),
+        mdl.write()?,
+    )?;
     Ok(())
 }